@@ -1,51 +1,193 @@
-use std::{error::Error, io::Cursor, path::PathBuf};
+use std::{error::Error, fs, io::Cursor, path::{Path, PathBuf}, time::SystemTime};
 
 use bytes::Bytes;
-use image::{io::Reader as ImageReader, DynamicImage};
+use image::{imageops, imageops::FilterType, io::Reader as ImageReader, DynamicImage, RgbImage};
+use log::{debug, warn};
+use md5::{Digest, Md5};
 use tempfile::NamedTempFile;
 
+use crate::config::Gui;
+use crate::playlist::Palette;
+
+use super::palette;
+
+const BACKGROUND_BLUR_SIGMA: f32 = 10.0;
+const BACKGROUND_BLUR_SCALE: u32 = 4;
+
 pub struct CoverProcessor {
     image: DynamicImage,
 }
 
 impl CoverProcessor {
-    pub fn new(bytes: Bytes) -> Self {
+    pub fn new(bytes: Bytes) -> Result<Self, Box<dyn Error>> {
         let image = ImageReader::new(Cursor::new(bytes))
-            .with_guessed_format()
-            .unwrap()
-            .decode()
-            .unwrap();
+            .with_guessed_format()?
+            .decode()?;
 
-        Self { image }
+        Ok(Self { image })
     }
 
-    pub fn generate_foreground(&self) -> Result<PathBuf, Box<dyn Error>> {
-        let tmp_path = Self::generate_tmp_file()?;
-        
-        self.image
-            .resize(320, 320, image::imageops::FilterType::Nearest)
-            .save_with_format(&tmp_path, image::ImageFormat::Png)
-            .unwrap();
-
-        Ok(tmp_path)
+    // Non-square covers are fitted and centered on a black square.
+    pub fn generate_foreground(&self, size: u32) -> DynamicImage {
+        let fitted = self.image.resize(size, size, FilterType::Lanczos3).to_rgb8();
+        let mut square = RgbImage::new(size, size);
+        imageops::overlay(&mut square, &fitted, ((size - fitted.width()) / 2) as i64, ((size - fitted.height()) / 2) as i64);
+        DynamicImage::ImageRgb8(square)
     }
 
-    pub fn generate_background(&self) -> Result<PathBuf, Box<dyn Error>> {
-        let tmp_path = Self::generate_tmp_file()?;
-        
-        self.image
+    // Blurred at a quarter of the size and scaled up, looks the same as a full size blur at a fraction of the cost.
+    pub fn generate_background(&self, size: u32) -> DynamicImage {
+        let small_size = (size / BACKGROUND_BLUR_SCALE).max(1);
+        let blur_sigma = BACKGROUND_BLUR_SIGMA * size as f32 / 1024.0 / BACKGROUND_BLUR_SCALE as f32;
+
+        let blurred = self.image
+            .resize_to_fill(small_size, small_size, FilterType::Triangle)
             .brighten(-75)
-            .resize(1024, 1024, image::imageops::FilterType::Nearest)
-            .blur(10.0)
-            .save_with_format(&tmp_path, image::ImageFormat::Png)
-            .unwrap();
+            .blur(blur_sigma);
+        DynamicImage::ImageRgb8(blurred.resize_exact(size, size, FilterType::CatmullRom).to_rgb8())
+    }
 
-        Ok(tmp_path)
+    pub fn generate_palette(&self) -> Palette {
+        palette::extract(&self.image)
     }
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+pub enum CoverVariant {
+    Foreground(u32),
+    Background(u32),
+}
 
-    fn generate_tmp_file() -> Result<PathBuf, Box<dyn Error>> {
-        let path = NamedTempFile::new()?.into_temp_path();
-        let image_tmp_path = path.keep()?.to_str().unwrap().to_string();
-        Ok(PathBuf::from(image_tmp_path))
+impl CoverVariant {
+    fn name(&self) -> String {
+        match self {
+            CoverVariant::Foreground(size) => format!("foreground-{}", size),
+            CoverVariant::Background(size) => format!("background-{}", size),
+        }
     }
-}
\ No newline at end of file
+    fn render(&self, processor: &CoverProcessor) -> DynamicImage {
+        match self {
+            CoverVariant::Foreground(size) => processor.generate_foreground(*size),
+            CoverVariant::Background(size) => processor.generate_background(*size),
+        }
+    }
+}
+
+pub struct CachedCover {
+    pub paths: Vec<PathBuf>,
+    pub palette: Palette,
+}
+
+// Rendered covers live on disk keyed by the cover URL, or by the picture itself for embedded covers,
+// so each album is decoded once and the directory never grows over its limit.
+#[derive(Clone)]
+pub struct CoverCache {
+    path: PathBuf,
+    max_size: u64,
+}
+
+impl CoverCache {
+    pub fn init(config: &Gui) -> Self {
+        let path = match config.cover_cache_path.as_str() {
+            "" => home::home_dir().unwrap_or_default().join(".cache/woodaudio/covers"),
+            path => PathBuf::from(path),
+        };
+        if let Err(err) = fs::create_dir_all(&path) {
+            warn!("[Cover cache] can not create {:?}: {:?}", path, err);
+        }
+
+        Self { path, max_size: config.cover_cache_max_mb * 1024 * 1024 }
+    }
+
+    pub fn url_key(cover_url: &str) -> String {
+        format!("{:x}", Md5::digest(cover_url.as_bytes()))
+    }
+
+    pub fn content_key(content: &[u8]) -> String {
+        format!("{:x}", Md5::digest(content))
+    }
+
+    // The source is only fetched and decoded when one of the variants or the palette is missing.
+    pub fn get_or_render(&self, key: &str, variants: &[CoverVariant], source: impl FnOnce() -> Result<Bytes, Box<dyn Error>>) -> Result<CachedCover, Box<dyn Error>> {
+        let paths: Vec<PathBuf> = variants.iter().map(|variant| self.file_name(key, *variant)).collect();
+        let palette_path = self.path.join(format!("{}-palette.json", key));
+        if paths.iter().all(|path| path.exists()) {
+            if let Some(palette) = Self::read_palette(&palette_path) {
+                paths.iter().chain([&palette_path]).for_each(|path| Self::touch(path));
+                return Ok(CachedCover { paths, palette });
+            }
+        }
+
+        let processor = CoverProcessor::new(source()?)?;
+        let palette = processor.generate_palette();
+        fs::write(&palette_path, serde_json::to_vec(&palette)?)?;
+
+        for (variant, path) in variants.iter().zip(&paths) {
+            if path.exists() {
+                Self::touch(path);
+                continue;
+            }
+
+            let tmp_file = NamedTempFile::new_in(&self.path)?;
+            variant.render(&processor).save_with_format(tmp_file.path(), image::ImageFormat::Png)?;
+            tmp_file.persist(path)?;
+            debug!("[Cover cache] rendered {:?}", path);
+        }
+
+        let mut keep = paths.clone();
+        keep.push(palette_path);
+        self.cleanup(&keep);
+        Ok(CachedCover { paths, palette })
+    }
+
+    fn read_palette(path: &Path) -> Option<Palette> {
+        serde_json::from_slice(&fs::read(path).ok()?).ok()
+    }
+
+    fn file_name(&self, key: &str, variant: CoverVariant) -> PathBuf {
+        self.path.join(format!("{}-{}.png", key, variant.name()))
+    }
+
+    fn touch(path: &Path) {
+        let _ = fs::File::options().write(true).open(path).and_then(|file| file.set_modified(SystemTime::now()));
+    }
+
+    // Least recently used covers go first, the ones just rendered are kept.
+    fn cleanup(&self, keep: &[PathBuf]) {
+        if self.max_size == 0 {
+            return;
+        }
+
+        let mut files: Vec<(PathBuf, u64, SystemTime)> = match fs::read_dir(&self.path) {
+            Ok(entries) => entries.flatten()
+                .filter_map(|entry| {
+                    let metadata = entry.metadata().ok()?;
+                    Some((entry.path(), metadata.len(), metadata.modified().ok()?))
+                })
+                .filter(|(path, _, _)| path.extension().is_some_and(|extension| extension == "png" || extension == "json"))
+                .collect(),
+            Err(err) => {
+                warn!("[Cover cache] can not read {:?}: {:?}", self.path, err);
+                return;
+            },
+        };
+
+        let mut total_size: u64 = files.iter().map(|(_, size, _)| size).sum();
+        files.sort_by_key(|(_, _, modified)| *modified);
+
+        for (path, size, _) in files {
+            if total_size <= self.max_size {
+                break;
+            }
+            if keep.contains(&path) {
+                continue;
+            }
+
+            match fs::remove_file(&path) {
+                Ok(()) => total_size -= size,
+                Err(err) => warn!("[Cover cache] remove {:?} error: {:?}", path, err),
+            }
+        }
+    }
+}
@@ -1,19 +1,50 @@
-use std::{error::Error, fs, sync::{Arc, Mutex}};
+use std::{error::Error, path::PathBuf, sync::{Arc, Mutex}, thread, time::Duration};
 
-use log::{debug, error, info};
+use bytes::Bytes;
+use crossbeam_channel::{bounded, Sender};
+
+use log::{debug, error, info, warn};
 use secular::normalized_lower_lay_string;
 
-use crate::{backend::cover::CoverProcessor, config::Config, playlist::{BufferedTrack, Cover, Track}};
-use crate::backend::storage::FileStorage;
-use super::{storage::{CacheRead, Exporter, FtpStorage}, tidal::TidalBackend, Backend};
+use crate::{backend::cover::{CoverCache, CoverVariant}, config::Config, playlist::{BufferedTrack, Cover, Track}};
+use crate::state::{Message, Notice, PlayerBus};
+use crate::util::lock;
+use crate::backend::storage::{FileStorage, FILE_STORAGE};
+use super::{storage::{embedded_cover, CacheLibrary, FileNameTemplate, FtpStorage, S3Storage, SftpStorage, Sidecars, Storage, Transcoder, WebDavStorage}, tidal::TidalBackend, upnp::UpnpBackend, Backend};
+#[cfg(feature = "smb")]
+use super::storage::SmbStorage;
+
+const SIDECAR_COVER_SIZE: u32 = 1280;
+// Jobs hold the whole FLAC, a full queue blocks the downloader until the exporters catch up.
+const EXPORT_QUEUE_SIZE: usize = 4;
+const FOREGROUND_LAYOUT_SIZE: f32 = 320.0;
+const BACKGROUND_LAYOUT_SIZE: f32 = 1024.0;
 
 #[derive(Clone)]
 pub struct Downloader {
-    storage_file: Arc<Mutex<Option<FileStorage>>>,
-    storage_ftp: Arc<Mutex<Option<FtpStorage>>>,
+    storages: Arc<Mutex<Vec<Box<dyn Storage>>>>,
+    file_name_template: FileNameTemplate,
     display_cover_background: bool,
     display_cover_foreground: bool,
     backend: TidalBackend,
+    upnp: Option<UpnpBackend>,
+    library: Option<CacheLibrary>,
+    transcoder: Option<Transcoder>,
+    sidecar_cover: bool,
+    sidecar_lyrics: bool,
+    cover_cache: CoverCache,
+    foreground_size: u32,
+    background_size: u32,
+    playerbus: PlayerBus,
+    exports: Option<Sender<ExportJob>>,
+}
+
+struct ExportJob {
+    track: Track,
+    bytes: Bytes,
+    cover_image: Option<Vec<u8>>,
+    file_name: String,
+    output_dir: Option<String>,
 }
 
 impl Track {
@@ -23,86 +54,126 @@ impl Track {
 }
 
 impl Downloader {
-    pub fn init(config: &Config, backend: TidalBackend) -> Self {
-        let storage_file = match config.exporter_file.enabled {
-            true => Some(FileStorage::init(config.exporter_file.clone())),
-            false => None,
-        };
+    pub fn init(config: &Config, backend: TidalBackend, upnp: Option<UpnpBackend>, playerbus: PlayerBus) -> Self {
+        let mut storages: Vec<Box<dyn Storage>> = vec![];
+        let mut library = None;
 
-        let storage_ftp = match config.exporter_ftp.enabled {
-            true => Some(FtpStorage::init(config.exporter_ftp.clone())),
-            false => None,
-        };
+        if config.exporter_file.enabled {
+            let file_storage = FileStorage::init(config.exporter_file.clone());
+            if config.exporter_file.scrub_interval_hours > 0 {
+                file_storage.start_scrub(Duration::from_secs(config.exporter_file.scrub_interval_hours * 3600));
+            }
+            library = Some(file_storage.library());
+            storages.push(Box::new(file_storage));
+        }
+        if config.exporter_ftp.enabled {
+            storages.push(Box::new(FtpStorage::init(config.exporter_ftp.clone())));
+        }
+        if config.exporter_webdav.enabled {
+            match WebDavStorage::init(config.exporter_webdav.clone()) {
+                Ok(storage) => storages.push(Box::new(storage)),
+                Err(err) => error!("[Downloader] WebDAV exporter disabled: {:?}", err),
+            }
+        }
+        if config.exporter_sftp.enabled {
+            storages.push(Box::new(SftpStorage::init(config.exporter_sftp.clone())));
+        }
+        #[cfg(feature = "smb")]
+        if config.exporter_smb.enabled {
+            storages.push(Box::new(SmbStorage::init(config.exporter_smb.clone())));
+        }
+        #[cfg(not(feature = "smb"))]
+        if config.exporter_smb.enabled {
+            warn!("[Downloader] SMB exporter ignored, built without the smb feature");
+        }
+        if config.exporter_s3.enabled {
+            match S3Storage::init(config.exporter_s3.clone()) {
+                Ok(storage) => storages.push(Box::new(storage)),
+                Err(err) => error!("[Downloader] S3 exporter disabled: {:?}", err),
+            }
+        }
+
+        let mut transcoder = None;
+        if config.transcoder.enabled {
+            // The local cache is indexed and played back, it always keeps FLAC.
+            if config.transcoder.exporters.iter().any(|name| name.eq_ignore_ascii_case(FILE_STORAGE)) {
+                warn!("[Downloader] File exporter can not receive transcoded copies, it keeps FLAC");
+            }
+            let (targets, flac_storages): (Vec<_>, Vec<_>) = storages.into_iter()
+                .partition(|storage| storage.name() != FILE_STORAGE && config.transcoder.exporters.iter().any(|name| name.eq_ignore_ascii_case(storage.name())));
+            storages = flac_storages;
+
+            match Transcoder::start(config.transcoder.clone(), targets) {
+                Ok(started) => transcoder = Some(started),
+                Err(err) => error!("[Downloader] transcoder disabled: {:?}", err),
+            }
+        }
 
-        Downloader {
-            storage_file: Arc::new(Mutex::new(storage_file)),
-            storage_ftp: Arc::new(Mutex::new(storage_ftp)),
+        // ui/app-window.slint is laid out for a 1024 px wide window and scaled by its width.
+        let cover_scale = config.gui.window_x as f32 / 1024.0;
+
+        let mut downloader = Downloader {
+            storages: Arc::new(Mutex::new(storages)),
+            file_name_template: FileNameTemplate::new(&config.exporter_layout.file_name_template),
             display_cover_background: config.gui.display_cover_background, 
             display_cover_foreground: config.gui.display_cover_foreground,
             backend,
-        }
+            upnp,
+            library,
+            transcoder,
+            sidecar_cover: config.exporter_file.enabled && config.exporter_file.sidecar_cover,
+            sidecar_lyrics: config.exporter_file.enabled && config.exporter_file.sidecar_lyrics,
+            cover_cache: CoverCache::init(&config.gui),
+            foreground_size: (FOREGROUND_LAYOUT_SIZE * cover_scale).round() as u32,
+            background_size: (BACKGROUND_LAYOUT_SIZE * cover_scale).round() as u32,
+            playerbus,
+            exports: None,
+        };
+        downloader.exports = Some(downloader.start_export_worker());
+        downloader
+    }
+
+    pub fn library(&self) -> Option<CacheLibrary> {
+        self.library.clone()
     }
 
     pub fn download_file(&mut self, track: Track) -> Result<BufferedTrack, Box<dyn Error>> {
-        if let Some(storage_file) = self.storage_file.lock().unwrap().as_mut() {
-            match storage_file.read_file(&track.file_name(), None) {
-                Ok(Some(file)) => {
-                    info!("[Storage] cache exists {:?}", track);
-                    return Ok(BufferedTrack {
-                        track: track.clone(),
-                        stream: file.clone(),
-                        cover: self.download_album_cover(track.album_image).unwrap_or_else(|_| Cover::empty()),
-                    })
-                },
-                _ => {
-                    info!("[Storage] cache empty or error for {:?}", track);
-                },
-            }
-        }
-        if let Some(ftp_storage) = self.storage_ftp.lock().unwrap().as_mut() {
-            match ftp_storage.read_file(&track.file_name(), None) {
+        let (output_dir, file_name) = self.export_path(&track);
+
+        for storage in lock(&self.storages).iter_mut() {
+            match storage.read_file(&track, &file_name, output_dir.as_deref()) {
                 Ok(Some(file)) => {
-                    info!("[Storage] cache exists {:?}", track);
+                    info!("[Storage {}] cache exists {:?}", storage.name(), track);
                     return Ok(BufferedTrack {
                         track: track.clone(),
                         stream: file.clone(),
-                        cover: self.download_album_cover(track.album_image).unwrap_or_else(|_| Cover::empty()),
+                        cover: self.download_album_cover(&track.id, self.cover_url(&track))
+                            .or_else(|_| self.prepare_embedded_cover(&file))
+                            .unwrap_or_else(|_| Cover::empty()),
                     })
                 },
                 _ => {
-                    info!("[Storage] cache empty or error for {:?}", track);
+                    info!("[Storage {}] cache empty or error for {:?}", storage.name(), track);
                 },
             }
         }
         for _ in 1..5 {
-            let bytes_response = self.backend.get_track(track.id.clone())?;
-
-            let cover = self.download_album_cover(track.album_image.clone()).unwrap_or_else(|_| Cover::empty());
-
-            if let Some(storage_file) = self.storage_file.lock().unwrap().as_mut() {
-                let export_bytes = bytes_response.clone();
-                let cover_image = if let Some(image_url) = cover.clone().foreground { Some(fs::read(image_url)?) } else { None };
-                match storage_file.write_file(track.clone(), export_bytes, &track.file_name(), None, cover_image) {
-                    Ok(()) => {
-                        info!("[Storage File] cache file wrote, track: {:?}", track);
-                    },
-                    Err(err) => {
-                        error!("[Storage File] cache file wrote error, track: {:?}, error: {:?}", track, err);
-                    },
-                }
-            }
+            let bytes_response = self.get_track(&track)?;
 
-            if let Some(storage_ftp) = self.storage_ftp.lock().unwrap().as_mut() {
-                let export_bytes = bytes_response.clone();
-                match storage_ftp.write_file(track.clone(), export_bytes, &track.file_name(), None, None) {
-                    Ok(()) => {
-                        info!("[Storage FTP] cache file wrote, track: {:?}", track);
-                    },
-                    Err(err) => {
-                        error!("[Storage FTP] cache file wrote error, track: {:?}, error: {:?}", track, err);
-                    },
-                }
-            }
+            let cover_url = self.cover_url(&track);
+            let cover_bytes = self.get_cover_bytes(&track.id, cover_url.clone()).ok();
+            let cover = cover_bytes.clone()
+                .map(|bytes| self.prepare_cover(&CoverCache::url_key(&cover_url), || Ok(bytes)).unwrap_or_else(|_| Cover::empty()))
+                .unwrap_or_else(Cover::empty);
+            let cover_image = cover_bytes.map(|bytes| bytes.to_vec());
+
+            self.queue_export(ExportJob {
+                track: track.clone(),
+                bytes: bytes_response.clone(),
+                cover_image,
+                file_name,
+                output_dir,
+            });
 
             return Ok(BufferedTrack {
                 track: track.clone(),
@@ -114,33 +185,194 @@ impl Downloader {
         Err("Track Download fail!".into())
     }
 
-    fn download_album_cover(&self, cover_url: String) -> Result<Cover, Box<dyn Error>> {
-        if !self.display_cover_background && !self.display_cover_foreground {
+    // Stores the track in every exporter without preparing it for playback, used by the offline sync.
+    pub fn cache_track(&mut self, track: Track, bytes_per_second: u64) -> Result<(), Box<dyn Error>> {
+        let (output_dir, file_name) = self.export_path(&track);
+        let bytes_response = self.backend.get_track_with_rate_limit(track.id.clone(), bytes_per_second)?;
+        let cover_image = self.get_cover_bytes(&track.id, self.cover_url(&track)).ok().map(|bytes| bytes.to_vec());
+
+        self.export(&track, &bytes_response, cover_image, &file_name, output_dir.as_deref())
+    }
+
+    // Album details, covers and lyrics are fetched and the exporters written on the export thread,
+    // the track goes to the player meanwhile.
+    fn start_export_worker(&self) -> Sender<ExportJob> {
+        let (sender, receiver) = bounded::<ExportJob>(EXPORT_QUEUE_SIZE);
+        let exporter = self.clone();
+
+        thread::Builder::new()
+            .name("Exporter".to_owned())
+            .spawn(move || {
+                for job in receiver {
+                    exporter.run_export(job);
+                }
+            })
+            .unwrap();
+        sender
+    }
+
+    fn queue_export(&self, job: ExportJob) {
+        match &self.exports {
+            Some(exports) => {
+                if exports.send(job).is_err() {
+                    error!("[Downloader] export queue closed, job dropped");
+                }
+            },
+            None => self.run_export(job),
+        }
+    }
+
+    fn run_export(&self, job: ExportJob) {
+        // The track still plays, the user only learns the copy is missing.
+        if let Err(err) = self.export(&job.track, &job.bytes, job.cover_image, &job.file_name, job.output_dir.as_deref()) {
+            let notice = Notice::warning("Exporter", format!("{} not saved: {}", job.track.full_name(), err));
+            self.playerbus.publish_message(Message::NotificationRaised(notice));
+        }
+    }
+
+    fn export(&self, track: &Track, bytes: &Bytes, cover_image: Option<Vec<u8>>, file_name: &str, output_dir: Option<&str>) -> Result<(), Box<dyn Error>> {
+        // Without exporters the album details would be requested for nothing.
+        if lock(&self.storages).is_empty() && self.transcoder.is_none() {
+            return Ok(());
+        }
+
+        let mut export_track = track.clone();
+        if !UpnpBackend::owns(&track.id) {
+            if let Err(err) = self.backend.complete_metadata(&mut export_track) {
+                warn!("[Downloader] album metadata unavailable, track: {:?}, error: {:?}", track, err);
+            }
+        }
+
+        let sidecars = self.sidecars(&export_track, cover_image.as_deref());
+        let mut failed: Vec<String> = vec![];
+        for storage in lock(&self.storages).iter_mut() {
+            match storage.write_file(export_track.clone(), bytes.clone(), file_name, output_dir, cover_image.clone()) {
+                Ok(()) => {
+                    info!("[Storage {}] cache file wrote, track: {:?}", storage.name(), track);
+                    if let Err(err) = storage.write_sidecars(&export_track, &sidecars) {
+                        warn!("[Storage {}] sidecar files error, track: {:?}, error: {:?}", storage.name(), track, err);
+                    }
+                },
+                Err(err) => {
+                    error!("[Storage {}] cache file wrote error, track: {:?}, error: {:?}", storage.name(), track, err);
+                    failed.push(storage.name().to_string());
+                },
+            }
+        }
+
+        if let Some(transcoder) = &self.transcoder {
+            transcoder.enqueue(export_track, bytes.clone(), file_name, output_dir, cover_image);
+        }
+
+        if failed.is_empty() { Ok(()) } else { Err(format!("export failed on: {}", failed.join(", ")).into()) }
+    }
+
+    // UPnP tracks have no lyrics and keep the cover served by the media server.
+    fn sidecars(&self, track: &Track, cover_image: Option<&[u8]>) -> Sidecars {
+        let mut sidecars = Sidecars::default();
+        let fallback_cover = || cover_image.map(Bytes::copy_from_slice);
+
+        if UpnpBackend::owns(&track.id) {
+            if self.sidecar_cover {
+                sidecars.cover = fallback_cover();
+            }
+            return sidecars;
+        }
+
+        if self.sidecar_cover {
+            sidecars.cover = self.backend.get_cover(TidalBackend::cover_url(&track.album_image, SIDECAR_COVER_SIZE))
+                .inspect_err(|err| warn!("[Downloader] full size cover unavailable, track: {:?}, error: {:?}", track, err))
+                .ok()
+                .or_else(fallback_cover);
+        }
+        if self.sidecar_lyrics {
+            sidecars.lyrics = self.backend.lyrics(&track.id)
+                .inspect_err(|err| warn!("[Downloader] lyrics unavailable, track: {:?}, error: {:?}", track, err))
+                .ok()
+                .flatten();
+        }
+        sidecars
+    }
+
+    fn export_path(&self, track: &Track) -> (Option<String>, String) {
+        if self.file_name_template.is_empty() {
+            return (None, track.file_name());
+        }
+
+        let export_path = self.file_name_template.render(track);
+        (export_path.output_dir, export_path.file_name)
+    }
+
+    fn get_track(&mut self, track: &Track) -> Result<Bytes, Box<dyn Error>> {
+        let bytes = match self.upnp.as_mut() {
+            Some(upnp) if UpnpBackend::owns(&track.id) => upnp.get_track(track.id.clone()),
+            _ => self.backend.get_track(track.id.clone()),
+        }?;
+        Ok(bytes)
+    }
+
+    fn download_album_cover(&self, track_id: &str, cover_url: String) -> Result<Cover, Box<dyn Error>> {
+        if cover_url.is_empty() {
+            return Err("track without cover url".into());
+        }
+        self.prepare_cover(&CoverCache::url_key(&cover_url), || self.get_cover_bytes(track_id, cover_url.clone()))
+    }
+
+    pub fn prepare_embedded_cover(&self, file: &Bytes) -> Result<Cover, Box<dyn Error>> {
+        match embedded_cover(file) {
+            Some(bytes) => self.prepare_cover(&CoverCache::content_key(&bytes), || Ok(bytes)),
+            None => Ok(Cover::empty()),
+        }
+    }
+
+    // Foreground only, as requested by the interfaces for covers of other tracks.
+    pub fn load_cover(&self, cover_url: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let cover = self.cover_cache.get_or_render(&CoverCache::url_key(cover_url), &[CoverVariant::Foreground(self.foreground_size)], || Ok(self.backend.get_cover(cover_url.to_string())?))?;
+        cover.paths.into_iter().next().ok_or_else(|| "cover not rendered".into())
+    }
+
+    // Tidal covers are fetched in the size shown on screen, the blurred background does not need more.
+    fn cover_url(&self, track: &Track) -> String {
+        if UpnpBackend::owns(&track.id) || track.album_image.is_empty() {
+            return track.album_image.clone();
+        }
+        TidalBackend::cover_url(&track.album_image, TidalBackend::cover_size_for(self.foreground_size))
+    }
+
+    fn get_cover_bytes(&self, track_id: &str, cover_url: String) -> Result<Bytes, Box<dyn Error>> {
+        let bytes = match &self.upnp {
+            Some(upnp) if UpnpBackend::owns(track_id) => upnp.get_cover(cover_url),
+            _ => self.backend.get_cover(cover_url),
+        }?;
+        Ok(bytes)
+    }
+
+    fn prepare_cover(&self, cover_key: &str, source: impl FnOnce() -> Result<Bytes, Box<dyn Error>>) -> Result<Cover, Box<dyn Error>> {
+        let mut variants: Vec<CoverVariant> = vec![];
+        if self.display_cover_foreground {
+            variants.push(CoverVariant::Foreground(self.foreground_size));
+        }
+        if self.display_cover_background {
+            variants.push(CoverVariant::Background(self.background_size));
+        }
+        if variants.is_empty() {
             return Ok(Cover::empty());
         }
 
-        debug!("[Downloader] Prepare cover '{}'...", cover_url);
-    
-        let bytes_response = self.backend.get_cover(cover_url.clone())?;
-        let cover = CoverProcessor::new(bytes_response);
+        debug!("[Downloader] Prepare cover '{}'...", cover_key);
 
-        let foreground = if self.display_cover_foreground {
-            Some(cover.generate_foreground()?.to_string_lossy().to_string())
-        } else {
-            None
-        };
+        let cover = self.cover_cache.get_or_render(cover_key, &variants, source)?;
+        let mut paths = cover.paths.iter().map(|path| path.to_string_lossy().to_string());
 
-        let background = if self.display_cover_background {
-            Some(cover.generate_background()?.to_string_lossy().to_string())
-        } else {
-            None
-        };
-        
-        debug!("[Downloader] Cover prepared '{}', foreground: {:?}, background: {:?}", cover_url, foreground, background);
-    
-        Ok(Cover { 
+        let foreground = if self.display_cover_foreground { paths.next() } else { None };
+        let background = if self.display_cover_background { paths.next() } else { None };
+
+        debug!("[Downloader] Cover prepared '{}', foreground: {:?}, background: {:?}, palette: {:?}", cover_key, foreground, background, cover.palette);
+
+        Ok(Cover {
             foreground,
-            background, 
+            background,
+            palette: Some(cover.palette),
         })
     }
-}
\ No newline at end of file
+}
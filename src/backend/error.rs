@@ -0,0 +1,61 @@
+use std::{error::Error, fmt};
+
+use crate::state::Notice;
+
+#[derive(Debug)]
+pub enum BackendError {
+    // Tidal has no session or the UPnP media server was not found.
+    Unavailable(String),
+    // Network failure or an error status from the server.
+    Request(Box<dyn Error>),
+    NotFound(String),
+    // The server answered, but not with what was expected.
+    InvalidResponse(String),
+}
+
+impl BackendError {
+    // Unavailable backends are expected while offline, everything else is a failure worth showing.
+    pub fn notice(&self, source: &str, operation: &str) -> Notice {
+        let message = format!("{} failed: {}", operation, self);
+        match self {
+            BackendError::Unavailable(_) => Notice::warning(source, message),
+            _ => Notice::error(source, message),
+        }
+    }
+    // Tidal may answer the same request later, a rejected one fails every time.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, BackendError::Unavailable(_) | BackendError::Request(_))
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackendError::Unavailable(detail) => write!(f, "unavailable, {}", detail),
+            BackendError::Request(err) => write!(f, "request error, {}", err),
+            BackendError::NotFound(detail) => write!(f, "not found, {}", detail),
+            BackendError::InvalidResponse(detail) => write!(f, "invalid response, {}", detail),
+        }
+    }
+}
+
+impl Error for BackendError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BackendError::Request(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Box<dyn Error>> for BackendError {
+    fn from(err: Box<dyn Error>) -> Self {
+        BackendError::Request(err)
+    }
+}
+
+impl From<reqwest::Error> for BackendError {
+    fn from(err: reqwest::Error) -> Self {
+        BackendError::Request(Box::new(err))
+    }
+}
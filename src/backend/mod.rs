@@ -1,29 +1,60 @@
-use std::{error::Error, sync::{Arc, Mutex}, time::Duration};
+use std::{error::Error, sync::{Arc, Mutex}};
 
 use bytes::Bytes;
+use log::{error, info, warn};
 
-use crate::{config::Config, state::{self, PlayerBus}, playlist::{BufferedTrack, Playlist, Track}};
-use crate::backend::cover::CoverProcessor;
-use crate::backend::storage::{CacheRandomRead, FileStorage};
+use crate::{config::Config, state::{self, CommandKind, PlayerBus}, playlist::{BufferedTrack, Cover, Playlist, Track}};
+use crate::backend::storage::{CacheRandomRead, FileNameTemplate, FileStorage};
 use crate::playlist::BufferedCover;
+use crate::util::lock;
 
-use self::{downloader::Downloader, tidal::TidalBackend};
+use self::{downloader::Downloader, error::BackendError, offline::{OfflineAction, OfflineBackend}, offline_sync::OfflineSync, tidal::TidalBackend, upnp::UpnpBackend};
 
+mod error;
 mod tidal;
+mod upnp;
+mod offline;
+mod offline_sync;
 mod downloader;
 mod cover;
+mod palette;
 mod storage;
 
+const COLD_START_TRACKS: usize = 5;
+
 pub trait Backend {
     fn init(config: &mut Config, player_bus: PlayerBus) -> Self;
-    fn discovery(&self, discovery_fn: impl Fn(Track));
-    fn get_track(&mut self, track_id: String) -> Result<Bytes, Box<dyn Error>>;
-    fn get_cover(&self, cover_url: String) -> Result<Bytes, Box<dyn Error>>;
-    fn discovery_radio(&self, id: &str, discovery_fn: impl Fn(Vec<Track>));
-    fn discovery_track(&self, id: &str, discovery_fn: impl Fn(Vec<Track>));
-    fn discovery_album(&self, id: &str, discovery_fn: impl Fn(Vec<Track>));
-    fn discovery_artist(&self, id: &str, discovery_fn: impl Fn(Vec<Track>));
-    fn add_track_to_favorites(&self, track_id: &str);
+    fn discovery(&self, discovery_fn: impl Fn(Track)) -> Result<(), BackendError>;
+    fn get_track(&mut self, track_id: String) -> Result<Bytes, BackendError>;
+    fn get_cover(&self, cover_url: String) -> Result<Bytes, BackendError>;
+    fn discovery_radio(&self, id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError>;
+    fn discovery_track(&self, id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError>;
+    fn discovery_album(&self, id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError>;
+    fn discovery_artist(&self, id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError>;
+    fn add_track_to_favorites(&self, track_id: &str) -> Result<(), BackendError>;
+}
+
+pub fn migrate_cache(config: &Config) {
+    let template = FileNameTemplate::new(&config.exporter_layout.file_name_template);
+    if template.is_empty() {
+        error!("[Cache] migration needs [Exporter] file_name_template in config");
+        return;
+    }
+
+    match FileStorage::init(config.exporter_file.clone()).migrate_layout(&template) {
+        Ok(moved) => info!("[Cache] migration finished, {} files moved", moved),
+        Err(err) => error!("[Cache] migration error: {:?}", err),
+    }
+}
+
+pub fn scrub_cache(config: &Config) {
+    if !config.exporter_file.enabled {
+        error!("[Cache] scrub needs enabled [ExporterFile] in config");
+        return;
+    }
+
+    let report = FileStorage::init(config.exporter_file.clone()).scrub();
+    info!("[Cache] scrub finished, {} valid, {} corrupt files quarantined", report.valid, report.corrupt);
 }
 
 #[derive(Clone)]
@@ -42,10 +73,22 @@ impl BackendInitialization {
         }
     }
     pub fn initialization(&self) {
+        // A restarted service module keeps the backends and the Tidal session.
+        if self.is_initialized() {
+            return;
+        }
+        if self.config.player.offline {
+            self.playerbus.publish_message(state::Message::UserSwitchOfflineMode(true));
+        }
+
+        let upnp = match self.config.upnp.enabled {
+            true => Some(UpnpBackend::init(&mut self.config.clone(), self.playerbus.clone())),
+            false => None,
+        };
         let tidal = TidalBackend::init(&mut self.config.clone(), self.playerbus.clone());
-        let mut backend = self.backend.lock().unwrap();
+        let mut backend = lock(&self.backend);
 
-        *backend = Some(BackendService::init(&self.config, tidal, self.playerbus.clone()));
+        *backend = Some(BackendService::init(&self.config, tidal, upnp, self.playerbus.clone()));
     }
     pub fn get_initialized(&self) -> BackendService {
         loop {
@@ -53,69 +96,152 @@ impl BackendInitialization {
                 break;
             }
         }
-        self.backend.lock().unwrap().clone().unwrap()
+        lock(&self.backend).clone().unwrap()
+    }
+    pub fn offline_sync(&self) -> Option<OfflineSync> {
+        let backend = self.get_initialized();
+        let library = backend.downloader.library()?;
+        Some(OfflineSync::new(self.config.clone(), backend.tidal, backend.downloader, library, self.playerbus.clone()))
     }
     fn is_initialized(&self) -> bool {
-        self.backend.lock().unwrap().is_some()
+        lock(&self.backend).is_some()
     }
 }
 
 #[derive(Clone)]
 pub struct BackendService {
     tidal: TidalBackend,
+    upnp: Option<UpnpBackend>,
+    offline: Option<OfflineBackend>,
+    offline_actions: Arc<Mutex<Vec<OfflineAction>>>,
     downloader: Downloader,
     playerbus: Arc<Mutex<PlayerBus>>,
     discovery_local: bool,
-    storage_local: Arc<Mutex<FileStorage>>,
 }
 
 impl BackendService {
-    fn init(config: &Config, tidal: TidalBackend, playerbus: PlayerBus) -> Self {
+    fn init(config: &Config, tidal: TidalBackend, upnp: Option<UpnpBackend>, playerbus: PlayerBus) -> Self {
+        let downloader = Downloader::init(config, tidal.clone(), upnp.clone(), playerbus.clone());
+
         Self { 
-            tidal: tidal.clone(),
+            tidal,
+            upnp,
+            offline: downloader.library().map(OfflineBackend::new),
+            offline_actions: Arc::new(Mutex::new(vec![])),
             playerbus: Arc::new(Mutex::new(playerbus)),
-            downloader: Downloader::init(config, tidal),
+            downloader,
             discovery_local: config.player.without_cold_start,
-            storage_local: Arc::new(Mutex::new(FileStorage::init(config.exporter_file.clone()))),
         }
     }
     pub fn discover(&self) {
         if self.discovery_local {
-            for _ in 0..5 {
-                let audio_file = self.storage_local.try_lock().unwrap().read_random_file(None).unwrap().unwrap();
-                self.playerbus.lock().unwrap().publish_message(state::Message::TrackDiscoveredLocally(audio_file));
+            match self.downloader.library().map(|mut library| library.read_random_files(COLD_START_TRACKS)) {
+                Some(Ok(tracks)) => {
+                    info!("[Backend] cold start with {} cached tracks", tracks.len());
+                    for mut audio_file in tracks {
+                        audio_file.cover = self.downloader.prepare_embedded_cover(&audio_file.stream).unwrap_or_else(|_| Cover::empty());
+                        lock(&self.playerbus).publish_message(state::Message::TrackDiscoveredLocally(audio_file));
+                    }
+                },
+                Some(Err(err)) => warn!("[Backend] cold start from cache failed: {:?}", err),
+                None => warn!("[Backend] cold start skipped, the local cache needs enabled [ExporterFile]"),
             }
         }
 
-        self.tidal.discovery(move |track| {
-            self.playerbus.lock().unwrap().publish_message(state::Message::TrackDiscovered(track));
+        if let Some(upnp) = &self.upnp {
+            let result = upnp.discovery(|track| {
+                lock(&self.playerbus).publish_message(state::Message::TrackDiscovered(track));
+            });
+            self.report("UPnP", "Discovery", result);
+        }
+
+        let result = match &self.offline {
+            Some(offline) if self.is_offline() => offline.discovery(|track| {
+                lock(&self.playerbus).publish_message(state::Message::TrackDiscovered(track));
+            }),
+            _ => self.tidal.discovery(move |track| {
+                lock(&self.playerbus).publish_message(state::Message::TrackDiscovered(track));
+            }),
+        };
+        self.report("Tidal", "Discovery", result);
+    }
+    // Failed operations are shown to the user, the caller carries on with the next command. Returns whether it succeeded.
+    fn report(&self, source: &str, operation: &str, result: Result<(), BackendError>) -> bool {
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                error!("[Backend] {} {} error: {}", source, operation, err);
+                lock(&self.playerbus).publish_message(state::Message::NotificationRaised(err.notice(source, operation)));
+                false
+            },
+        }
+    }
+    // The user commands pause before loading, the loaded message skips to the new tracks,
+    // after a failure the paused track plays on.
+    fn finish_loading(&self, loaded: bool, message: state::Message) {
+        let playerbus = lock(&self.playerbus);
+        match loaded {
+            true => playerbus.publish_message(message),
+            false => playerbus.publish_command(state::Command::Play),
+        }
+    }
+    fn source_name(&self, track_id: &str) -> &'static str {
+        if self.upnp.is_some() && UpnpBackend::owns(track_id) {
+            "UPnP"
+        } else if self.offline.is_some() && self.is_offline() {
+            "Offline"
+        } else {
+            "Tidal"
+        }
+    }
+    fn is_offline(&self) -> bool {
+        lock(&self.playerbus).read_state().offline.enabled()
+    }
+    fn queue_offline_action(&self, action: OfflineAction) {
+        let mut offline_actions = lock(&self.offline_actions);
+        info!("[Offline] queue action until Tidal is back: {:?}", action);
+        offline_actions.push(action);
+        lock(&self.playerbus).publish_message(state::Message::OfflineActionsQueued(offline_actions.len()));
+    }
+    fn replay_offline_actions(&self) {
+        let actions: Vec<OfflineAction> = lock(&self.offline_actions).drain(..).collect();
+        let failed = offline::replay_actions(actions, &self.tidal, |_, result| {
+            self.report("Tidal", "Like", result);
         });
+
+        let mut offline_actions = lock(&self.offline_actions);
+        offline_actions.splice(0..0, failed);
+        lock(&self.playerbus).publish_message(state::Message::OfflineActionsQueued(offline_actions.len()));
     }
     pub fn download(&mut self, track: Track) -> Result<BufferedTrack, Box<dyn Error>> {
-        self.downloader.download_file(track)
+        self.downloader.download_file(track.clone()).inspect_err(|err| {
+            let notice = state::Notice::error("Downloader", format!("{} download failed: {}", track.full_name(), err));
+            lock(&self.playerbus).publish_message(state::Message::NotificationRaised(notice));
+        })
     }
     pub fn listen_commands(self, playlist: Playlist) {
-        let channel = self.playerbus.lock().unwrap().register_command_channel(
+        let channel = lock(&self.playerbus).register_command_channel(
             vec![
-                "AddTracksToPlaylist".to_string(), 
-                "AddTracksToPlaylistForce".to_string(),
-                "AddBufferedTracksToPlaylist".to_string(),
-                "Radio".to_string(), 
-                "PlayTrackForce".to_string(), 
-                "PlayAlbumForce".to_string(), 
-                "PlayArtistForce".to_string(), 
-                "Like".to_string(),
-                "LoadLikedAlbum".to_string(),
-                "LoadCover".to_string(),
+                CommandKind::AddTracksToPlaylist,
+                CommandKind::AddTracksToPlaylistForce,
+                CommandKind::AddBufferedTracksToPlaylist,
+                CommandKind::Radio,
+                CommandKind::PlayTrackForce,
+                CommandKind::PlayAlbumForce,
+                CommandKind::PlayArtistForce,
+                CommandKind::Like,
+                CommandKind::LoadLikedAlbum,
+                CommandKind::LoadCover,
+                CommandKind::ReplayOfflineActions,
             ]
         );
 
         let discovery_fn = |tracks| {
-            self.playerbus.lock().unwrap().publish_message(state::Message::TracksDiscoveredWithHighPriority(tracks));
+            lock(&self.playerbus).publish_message(state::Message::TracksDiscoveredWithHighPriority(tracks));
         };
 
         loop {
-            let command = channel.read_command();
+            let command = channel.recv_command();
 
             match command {
                 Some(state::Command::AddTracksToPlaylist(tracks)) => {
@@ -128,32 +254,76 @@ impl BackendService {
                     playlist.push_buffered(tracks);
                 },
                 Some(state::Command::Radio(track_id)) => {
-                    let _ = self.tidal.discovery_radio(&track_id, discovery_fn);
-                    self.playerbus.lock().unwrap().publish_message(state::Message::RadioTracksLoaded);
+                    let result = match &self.upnp {
+                        Some(upnp) if UpnpBackend::owns(&track_id) => upnp.discovery_radio(&track_id, discovery_fn),
+                        _ => match &self.offline {
+                            Some(offline) if self.is_offline() => offline.discovery_radio(&track_id, discovery_fn),
+                            _ => self.tidal.discovery_radio(&track_id, discovery_fn),
+                        },
+                    };
+                    let loaded = self.report(self.source_name(&track_id), "Radio", result);
+                    self.finish_loading(loaded, state::Message::RadioTracksLoaded);
                 },
                 Some(state::Command::PlayTrackForce(track_id)) => {
-                    let _ = self.tidal.discovery_track(&track_id, discovery_fn);
-                    self.playerbus.lock().unwrap().publish_message(state::Message::TrackLoaded);
+                    let result = match &self.upnp {
+                        Some(upnp) if UpnpBackend::owns(&track_id) => upnp.discovery_track(&track_id, discovery_fn),
+                        _ => match &self.offline {
+                            Some(offline) if self.is_offline() => offline.discovery_track(&track_id, discovery_fn),
+                            _ => self.tidal.discovery_track(&track_id, discovery_fn),
+                        },
+                    };
+                    let loaded = self.report(self.source_name(&track_id), "Track", result);
+                    self.finish_loading(loaded, state::Message::TrackLoaded);
                 },
                 Some(state::Command::PlayAlbumForce(track_id)) => {
-                    let _ = self.tidal.discovery_album(&track_id, discovery_fn);
-                    self.playerbus.lock().unwrap().publish_message(state::Message::AlbumTracksLoaded);
+                    let result = match &self.upnp {
+                        Some(upnp) if UpnpBackend::owns(&track_id) => upnp.discovery_album(&track_id, discovery_fn),
+                        _ => match &self.offline {
+                            Some(offline) if self.is_offline() => offline.discovery_album(&track_id, discovery_fn),
+                            _ => self.tidal.discovery_album(&track_id, discovery_fn),
+                        },
+                    };
+                    let loaded = self.report(self.source_name(&track_id), "Album", result);
+                    self.finish_loading(loaded, state::Message::AlbumTracksLoaded);
                 },
                 Some(state::Command::PlayArtistForce(track_id)) => {
-                    let _ = self.tidal.discovery_artist(&track_id, discovery_fn);
-                    self.playerbus.lock().unwrap().publish_message(state::Message::ArtistTracksLoaded);
+                    let result = match &self.upnp {
+                        Some(upnp) if UpnpBackend::owns(&track_id) => upnp.discovery_artist(&track_id, discovery_fn),
+                        _ => match &self.offline {
+                            Some(offline) if self.is_offline() => offline.discovery_artist(&track_id, discovery_fn),
+                            _ => self.tidal.discovery_artist(&track_id, discovery_fn),
+                        },
+                    };
+                    let loaded = self.report(self.source_name(&track_id), "Artist", result);
+                    self.finish_loading(loaded, state::Message::ArtistTracksLoaded);
                 },
                 Some(state::Command::Like(track_id)) => {
-                    let _ = self.tidal.add_track_to_favorites(&track_id);
-                    self.playerbus.lock().unwrap().publish_message(state::Message::TrackAddedToFavorites);
+                    if let Some(library) = self.downloader.library() {
+                        library.set_liked(&track_id);
+                    }
+                    let liked = if self.is_offline() {
+                        self.queue_offline_action(OfflineAction::Like(track_id));
+                        true
+                    } else {
+                        let result = self.tidal.add_track_to_favorites(&track_id);
+                        self.report("Tidal", "Like", result)
+                    };
+                    if liked {
+                        lock(&self.playerbus).publish_message(state::Message::TrackAddedToFavorites);
+                    }
                 },
                 Some(state::Command::LoadCover(cover_url)) => {
-                    let cover_path = CoverProcessor::new(self.tidal.get_cover(cover_url.clone()).unwrap()).generate_foreground().unwrap();
-                    self.playerbus.lock().unwrap().publish_message(state::Message::CoverLoaded(BufferedCover { url: cover_url.clone(), path: cover_path.to_str().unwrap().to_string() }))
+                    match self.downloader.load_cover(&cover_url) {
+                        Ok(cover_path) => {
+                            lock(&self.playerbus).publish_message(state::Message::CoverLoaded(BufferedCover { url: cover_url.clone(), path: cover_path.to_string_lossy().to_string() }))
+                        },
+                        Err(err) => warn!("[Backend] cover '{}' unavailable: {:?}", cover_url, err),
+                    }
                 },
-                _ => {
-                    std::thread::sleep(Duration::from_millis(500));
+                Some(state::Command::ReplayOfflineActions) => {
+                    self.replay_offline_actions();
                 },
+                _ => {},
             }
         }
     }
@@ -0,0 +1,179 @@
+use log::info;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+
+use crate::playlist::Track;
+
+use super::{error::BackendError, storage::CacheLibrary, Backend};
+
+const RADIO_SIZE: usize = 50;
+
+#[derive(Clone)]
+pub struct OfflineBackend {
+    library: CacheLibrary,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub enum OfflineAction {
+    Like(String),
+}
+
+impl OfflineAction {
+    pub fn replay(&self, backend: &impl Backend) -> Result<(), BackendError> {
+        match self {
+            OfflineAction::Like(track_id) => backend.add_track_to_favorites(track_id),
+        }
+    }
+}
+
+// Replays the queued actions and returns the ones worth another try, a rejected action would fail every time.
+pub fn replay_actions(actions: Vec<OfflineAction>, backend: &impl Backend, mut report: impl FnMut(&OfflineAction, Result<(), BackendError>)) -> Vec<OfflineAction> {
+    let mut failed: Vec<OfflineAction> = vec![];
+    for action in actions {
+        info!("[Offline] replay action: {:?}", action);
+        let result = action.replay(backend);
+        let retry = matches!(&result, Err(err) if err.is_transient());
+        report(&action, result);
+        if retry {
+            failed.push(action);
+        }
+    }
+    failed
+}
+
+impl OfflineBackend {
+    pub fn new(library: CacheLibrary) -> Self {
+        Self { library }
+    }
+
+    pub fn discovery(&self, discovery_fn: impl Fn(Track)) -> Result<(), BackendError> {
+        let tracks = shuffled(self.library.tracks());
+        info!("[Offline] Discover {} tracks from cache", tracks.len());
+
+        for track in tracks {
+            discovery_fn(track);
+        }
+        Ok(())
+    }
+
+    // Tracks of the same artist and album go first, the rest of the radio is filled randomly.
+    pub fn discovery_radio(&self, track_id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+        let seed = match self.library.track(track_id) {
+            Some(track) => track,
+            None => {
+                info!("[Offline] Track {} not cached, random radio", track_id);
+                discovery_fn(shuffled(self.library.tracks()).into_iter().take(RADIO_SIZE).collect());
+                return Ok(());
+            },
+        };
+
+        let (related, others): (Vec<Track>, Vec<Track>) = shuffled(self.library.tracks()).into_iter()
+            .filter(|track| track.id != seed.id)
+            .partition(|track| track.artist_name == seed.artist_name || track.album_name == seed.album_name);
+
+        let tracks: Vec<Track> = related.into_iter().chain(others).take(RADIO_SIZE).collect();
+        info!("[Offline] Discover radio for track: {}, {} tracks", track_id, tracks.len());
+        discovery_fn(tracks);
+        Ok(())
+    }
+
+    pub fn discovery_track(&self, track_id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+        match self.library.track(track_id) {
+            Some(track) => self.discovery_radio(track_id, |tracks| discovery_fn([vec![track.clone()], tracks].concat())),
+            None => self.discovery_radio(track_id, discovery_fn),
+        }
+    }
+
+    pub fn discovery_album(&self, album_id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+        let mut tracks: Vec<Track> = self.library.tracks().into_iter()
+            .filter(|track| track.metadata.album_id == album_id)
+            .collect();
+        tracks.sort_by_key(|track| (track.metadata.disc_number, track.metadata.track_number));
+
+        info!("[Offline] Discover {} cached tracks from album: {}", tracks.len(), album_id);
+        discovery_fn(tracks);
+        Ok(())
+    }
+
+    pub fn discovery_artist(&self, artist_id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+        let tracks: Vec<Track> = shuffled(self.library.tracks()).into_iter()
+            .filter(|track| track.metadata.artist_id == artist_id)
+            .collect();
+
+        info!("[Offline] Discover {} cached tracks from artist: {}", tracks.len(), artist_id);
+        discovery_fn(tracks);
+        Ok(())
+    }
+}
+
+fn shuffled(mut tracks: Vec<Track>) -> Vec<Track> {
+    tracks.shuffle(&mut thread_rng());
+    tracks
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{replay_actions, OfflineAction};
+    use crate::backend::{error::BackendError, Backend};
+    use crate::config::Config;
+    use crate::playlist::Track;
+    use crate::state::PlayerBus;
+
+    // Rejects every like with the given error.
+    struct StubBackend {
+        error: fn(&str) -> BackendError,
+    }
+
+    impl Backend for StubBackend {
+        fn init(_: &mut Config, _: PlayerBus) -> Self {
+            Self { error: |track_id| BackendError::NotFound(track_id.to_string()) }
+        }
+        fn discovery(&self, _: impl Fn(Track)) -> Result<(), BackendError> {
+            Ok(())
+        }
+        fn get_track(&mut self, track_id: String) -> Result<Bytes, BackendError> {
+            Err(BackendError::NotFound(track_id))
+        }
+        fn get_cover(&self, cover_url: String) -> Result<Bytes, BackendError> {
+            Err(BackendError::NotFound(cover_url))
+        }
+        fn discovery_radio(&self, _: &str, _: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+            Ok(())
+        }
+        fn discovery_track(&self, _: &str, _: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+            Ok(())
+        }
+        fn discovery_album(&self, _: &str, _: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+            Ok(())
+        }
+        fn discovery_artist(&self, _: &str, _: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+            Ok(())
+        }
+        fn add_track_to_favorites(&self, track_id: &str) -> Result<(), BackendError> {
+            Err((self.error)(track_id))
+        }
+    }
+
+    #[test]
+    fn rejected_like_is_not_requeued() {
+        let backend = StubBackend { error: |track_id| BackendError::NotFound(track_id.to_string()) };
+        let mut reported = 0;
+
+        let failed = replay_actions(vec![OfflineAction::Like("1".to_string())], &backend, |_, _| reported += 1);
+
+        assert!(failed.is_empty());
+        assert_eq!(reported, 1);
+    }
+
+    #[test]
+    fn like_is_requeued_while_tidal_is_unreachable() {
+        let backend = StubBackend { error: |_| BackendError::Unavailable("no Tidal session, offline".to_string()) };
+
+        let failed = replay_actions(vec![OfflineAction::Like("1".to_string()), OfflineAction::Like("2".to_string())], &backend, |_, _| {});
+
+        assert_eq!(failed.len(), 2);
+    }
+}
@@ -0,0 +1,190 @@
+use std::{collections::HashSet, error::Error, time::{Duration, Instant}};
+
+use chrono::{Local, NaiveTime};
+use log::{error, info, warn};
+
+use crate::config::Config;
+use crate::playlist::Track;
+use crate::state::{Command, CommandKind, Message, Notice, PlayerBus};
+
+use super::{downloader::Downloader, storage::CacheLibrary, tidal::TidalBackend};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub enum SyncSource {
+    Favorites,
+    Album(String),
+    Artist(String),
+    Playlist(String),
+}
+
+impl SyncSource {
+    // Accepts Tidal share links (https://tidal.com/album/<id>) or "favorites".
+    pub fn from_url(url: &str) -> Option<Self> {
+        if url == "favorites" {
+            return Some(SyncSource::Favorites);
+        }
+
+        let mut segments = url.trim_end_matches('/').rsplit('/');
+        let id = segments.next()?.to_string();
+        match segments.next()? {
+            "album" => Some(SyncSource::Album(id)),
+            "artist" => Some(SyncSource::Artist(id)),
+            "playlist" => Some(SyncSource::Playlist(id)),
+            _ => None,
+        }
+    }
+}
+
+pub struct OfflineSync {
+    config: Config,
+    tidal: TidalBackend,
+    downloader: Downloader,
+    library: CacheLibrary,
+    playerbus: PlayerBus,
+    last_sync: Option<Instant>,
+}
+
+impl OfflineSync {
+    pub fn new(config: Config, tidal: TidalBackend, downloader: Downloader, library: CacheLibrary, playerbus: PlayerBus) -> Self {
+        Self { config, tidal, downloader, library, playerbus, last_sync: None }
+    }
+
+    pub fn run(mut self) {
+        let channel = self.playerbus.register_command_channel(vec![CommandKind::MarkAvailableOffline]);
+
+        loop {
+            // A sync stopped by the window or by going offline is resumed at the next check.
+            if self.is_due() && self.sync() {
+                self.last_sync = Some(Instant::now());
+            }
+
+            if let Some(Command::MarkAvailableOffline(url)) = channel.recv_command_timeout(CHECK_INTERVAL) {
+                match SyncSource::from_url(&url) {
+                    Some(source) => self.mark(source),
+                    None => warn!("[Offline sync] unsupported url: {}", url),
+                }
+            }
+        }
+    }
+
+    fn mark(&mut self, source: SyncSource) {
+        info!("[Offline sync] mark available offline: {:?}", source);
+
+        let mut config = self.config.reload();
+        let settings = &mut config.offline_sync;
+        let list = match &source {
+            SyncSource::Favorites => { settings.favorites = true; None },
+            SyncSource::Album(id) => Some((&mut settings.albums, id)),
+            SyncSource::Artist(id) => Some((&mut settings.artists, id)),
+            SyncSource::Playlist(id) => Some((&mut settings.playlists, id)),
+        };
+        if let Some((list, id)) = list {
+            if !list.contains(id) {
+                list.push(id.clone());
+            }
+        }
+        // The sync still runs in this session, the mark is lost on restart.
+        if let Err(err) = config.save() {
+            error!("[Offline sync] config save error: {:?}", err);
+            let notice = Notice::error("Offline sync", format!("{:?} not saved in config: {}", source, err));
+            self.playerbus.publish_message(Message::NotificationRaised(notice));
+        }
+
+        self.config.offline_sync = config.offline_sync;
+        self.last_sync = None;
+    }
+
+    fn is_due(&self) -> bool {
+        let settings = &self.config.offline_sync;
+        let interval = Duration::from_secs(settings.interval_hours * 3600);
+
+        settings.enabled
+            && self.last_sync.is_none_or(|last_sync| last_sync.elapsed() >= interval)
+            && !self.playerbus.read_state().offline.enabled()
+            && in_window(&settings.window)
+    }
+
+    fn sources(&self) -> Vec<SyncSource> {
+        let settings = &self.config.offline_sync;
+        let mut sources: Vec<SyncSource> = vec![];
+
+        if settings.favorites {
+            sources.push(SyncSource::Favorites);
+        }
+        sources.extend(settings.albums.iter().cloned().map(SyncSource::Album));
+        sources.extend(settings.artists.iter().cloned().map(SyncSource::Artist));
+        sources.extend(settings.playlists.iter().cloned().map(SyncSource::Playlist));
+        sources
+    }
+
+    fn tracks(&self, source: &SyncSource) -> Result<Vec<Track>, Box<dyn Error>> {
+        match source {
+            SyncSource::Favorites => self.tidal.favorite_tracks(),
+            SyncSource::Album(id) => self.tidal.album_tracks(id),
+            SyncSource::Artist(id) => self.tidal.artist_tracks(id),
+            SyncSource::Playlist(id) => self.tidal.playlist_tracks(id),
+        }
+    }
+
+    // False when the sync stopped before all tracks were downloaded.
+    fn sync(&mut self) -> bool {
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut missing: Vec<Track> = vec![];
+        let mut favorites: Vec<String> = vec![];
+
+        for source in self.sources() {
+            match self.tracks(&source) {
+                Ok(tracks) => missing.extend(tracks.into_iter()
+                    .inspect(|track| if let SyncSource::Favorites = source { favorites.push(track.id.clone()) })
+                    .filter(|track| seen.insert(track.id.clone()))
+                    .filter(|track| !self.library.contains(&track.id))),
+                Err(err) => error!("[Offline sync] {:?} tracks unavailable: {:?}", source, err),
+            }
+        }
+
+        info!("[Offline sync] {} tracks to download", missing.len());
+        self.playerbus.publish_message(Message::OfflineSyncStarted(missing.len()));
+
+        let bytes_per_second = self.config.offline_sync.max_kbps * 1024 / 8;
+        let mut completed = true;
+        for track in missing {
+            if self.playerbus.read_state().offline.enabled() || !in_window(&self.config.offline_sync.window) {
+                info!("[Offline sync] stopped, offline or outside of the sync window");
+                completed = false;
+                break;
+            }
+
+            self.playerbus.publish_message(Message::OfflineSyncTrackStarted(track.full_name()));
+            let result = self.downloader.cache_track(track.clone(), bytes_per_second);
+            if let Err(err) = &result {
+                error!("[Offline sync] download error, track: {:?}, error: {:?}", track, err);
+            }
+            self.playerbus.publish_message(Message::OfflineSyncTrackFinished(result.is_ok()));
+        }
+
+        for track_id in favorites {
+            self.library.set_liked(&track_id);
+        }
+        self.playerbus.publish_message(Message::OfflineSyncFinished);
+        completed
+    }
+}
+
+// Window format is "HH:MM-HH:MM", it may wrap over midnight.
+fn in_window(window: &str) -> bool {
+    let Some((start, end)) = window.split_once('-') else { return true };
+    let (Ok(start), Ok(end)) = (NaiveTime::parse_from_str(start.trim(), "%H:%M"), NaiveTime::parse_from_str(end.trim(), "%H:%M")) else {
+        warn!("[Offline sync] invalid window '{}', ignored", window);
+        return true;
+    };
+
+    let now = Local::now().time();
+    if start <= end {
+        start <= now && now < end
+    } else {
+        now >= start || now < end
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use image::{imageops::FilterType, DynamicImage};
+
+use crate::playlist::Palette;
+
+const SAMPLE_SIZE: u32 = 64;
+// The background is the cover darkened by `brighten(-75)`, text and accent are checked against that.
+const BACKGROUND_DARKEN: f32 = 75.0;
+const MIN_ACCENT_CONTRAST: f32 = 3.0;
+
+type Rgb = [u8; 3];
+
+#[derive(Default)]
+struct Bucket {
+    sum: [u64; 3],
+    count: u64,
+}
+
+impl Bucket {
+    fn color(&self) -> Rgb {
+        self.sum.map(|channel| (channel / self.count.max(1)) as u8)
+    }
+}
+
+pub fn extract(image: &DynamicImage) -> Palette {
+    let buckets = quantize(image);
+
+    let dominant = buckets.iter()
+        .max_by_key(|bucket| bucket.count)
+        .map(Bucket::color)
+        .unwrap_or([0, 0, 0]);
+    let vibrant = pick(&buckets, |(saturation, lightness)| saturation > 0.35 && (0.3..0.8).contains(&lightness), |(saturation, _)| saturation * saturation)
+        .unwrap_or(dominant);
+    let muted = pick(&buckets, |(saturation, lightness)| saturation < 0.35 && (0.2..0.7).contains(&lightness), |_| 1.0)
+        .unwrap_or(dominant);
+
+    let background = dominant.map(|channel| (channel as f32 - BACKGROUND_DARKEN).max(0.0) as u8);
+    let text = text_color(background);
+    let shadow = if text == [255, 255, 255] { [0, 0, 0] } else { [255, 255, 255] };
+
+    Palette {
+        dominant,
+        vibrant: with_contrast(vibrant, background, MIN_ACCENT_CONTRAST, text),
+        muted,
+        text,
+        shadow,
+    }
+}
+
+// 4 bits per channel, enough to group similar shades of a cover.
+fn quantize(image: &DynamicImage) -> Vec<Bucket> {
+    let sample = image.resize_exact(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle).to_rgb8();
+    let mut buckets: HashMap<u16, Bucket> = HashMap::new();
+
+    for pixel in sample.pixels() {
+        let key = pixel.0.iter().fold(0u16, |key, channel| (key << 4) | (*channel >> 4) as u16);
+        let bucket = buckets.entry(key).or_default();
+        for (sum, channel) in bucket.sum.iter_mut().zip(pixel.0) {
+            *sum += channel as u64;
+        }
+        bucket.count += 1;
+    }
+
+    buckets.into_values().collect()
+}
+
+fn pick(buckets: &[Bucket], accept: impl Fn((f32, f32)) -> bool, weight: impl Fn((f32, f32)) -> f32) -> Option<Rgb> {
+    buckets.iter()
+        .map(|bucket| (bucket, saturation_lightness(bucket.color())))
+        .filter(|(_, hsl)| accept(*hsl))
+        .max_by(|(a, a_hsl), (b, b_hsl)| (a.count as f32 * weight(*a_hsl)).total_cmp(&(b.count as f32 * weight(*b_hsl))))
+        .map(|(bucket, _)| bucket.color())
+}
+
+fn saturation_lightness(color: Rgb) -> (f32, f32) {
+    let [r, g, b] = color.map(|channel| channel as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+
+    let saturation = if max == min {
+        0.0
+    } else if lightness > 0.5 {
+        (max - min) / (2.0 - max - min)
+    } else {
+        (max - min) / (max + min)
+    };
+    (saturation, lightness)
+}
+
+// WCAG relative luminance and contrast ratio.
+fn luminance(color: Rgb) -> f32 {
+    let [r, g, b] = color.map(|channel| {
+        let channel = channel as f32 / 255.0;
+        if channel <= 0.03928 { channel / 12.92 } else { ((channel + 0.055) / 1.055).powf(2.4) }
+    });
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+fn contrast(a: Rgb, b: Rgb) -> f32 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+fn text_color(background: Rgb) -> Rgb {
+    let white = [255, 255, 255];
+    let black = [0, 0, 0];
+    if contrast(white, background) >= contrast(black, background) {
+        white
+    } else {
+        black
+    }
+}
+
+// Mixes the color towards the text color until it stands out from the background.
+fn with_contrast(color: Rgb, background: Rgb, min_contrast: f32, towards: Rgb) -> Rgb {
+    let mut mixed = color;
+    for step in 1..=10 {
+        if contrast(mixed, background) >= min_contrast {
+            break;
+        }
+        let ratio = step as f32 / 10.0;
+        mixed = [0, 1, 2].map(|index| (color[index] as f32 * (1.0 - ratio) + towards[index] as f32 * ratio) as u8);
+    }
+    mixed
+}
@@ -0,0 +1,297 @@
+use std::{collections::HashMap, error::Error, fs, path::{Path, PathBuf}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use log::{info, warn};
+use metaflac::Tag;
+use serde::{Deserialize, Serialize};
+
+use crate::playlist::{Track, TrackMetadata};
+
+use super::tagging::{read_track, TIDAL_ID};
+
+const INDEX_FILE_NAME: &str = ".index.json";
+const ORPHAN_ID_PREFIX: &str = "file:";
+// Play statistics of cache hits are written at most this often, a lost update only skews the eviction order.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub enum EvictionPolicy {
+    LeastRecentlyUsed,
+    LeastPlayed,
+}
+
+impl EvictionPolicy {
+    pub fn from_config(value: &str) -> Self {
+        match value {
+            "least_played" => EvictionPolicy::LeastPlayed,
+            _ => EvictionPolicy::LeastRecentlyUsed,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub track_id: String,
+    pub file_name: String,
+    pub title: String,
+    pub artist_name: String,
+    pub album_name: String,
+    #[serde(default)]
+    pub album_id: String,
+    #[serde(default)]
+    pub artist_id: String,
+    #[serde(default)]
+    pub disc_number: u32,
+    #[serde(default)]
+    pub track_number: u32,
+    #[serde(default)]
+    pub duration: u64,
+    pub size: u64,
+    pub added_at: u64,
+    pub last_played: u64,
+    pub play_count: u32,
+    #[serde(default)]
+    pub liked: bool,
+}
+
+impl CacheEntry {
+    pub fn new(track: &Track, file_name: &str, size: u64) -> Self {
+        Self {
+            track_id: track.id.clone(),
+            file_name: file_name.to_string(),
+            title: track.title.clone(),
+            artist_name: track.artist_name.clone(),
+            album_name: track.album_name.clone(),
+            album_id: track.metadata.album_id.clone(),
+            artist_id: track.metadata.artist_id.clone(),
+            disc_number: track.metadata.disc_number,
+            track_number: track.metadata.track_number,
+            duration: track.duration.as_secs(),
+            size,
+            added_at: now(),
+            last_played: now(),
+            play_count: 1,
+            liked: false,
+        }
+    }
+
+    // Liked and recently played tracks are preferred, everything else keeps a base chance.
+    pub fn cold_start_weight(&self) -> f64 {
+        let days_since_played = now().saturating_sub(self.last_played) as f64 / 86400.0;
+        let recency = if self.last_played > 0 { 3.0 * (-days_since_played / 30.0).exp() } else { 0.0 };
+        let liked = if self.liked { 5.0 } else { 0.0 };
+
+        1.0 + liked + recency + self.play_count.min(10) as f64 * 0.3
+    }
+
+    pub fn to_track(&self) -> Track {
+        Track {
+            id: self.track_id.clone(),
+            title: self.title.clone(),
+            artist_name: self.artist_name.clone(),
+            album_name: self.album_name.clone(),
+            album_image: "".to_string(),
+            duration: Duration::from_secs(self.duration),
+            metadata: TrackMetadata {
+                album_id: self.album_id.clone(),
+                artist_id: self.artist_id.clone(),
+                disc_number: self.disc_number,
+                track_number: self.track_number,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CacheIndex {
+    path: PathBuf,
+    entries: HashMap<String, CacheEntry>,
+    dirty: bool,
+    saved_at: Instant,
+}
+
+impl CacheIndex {
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(INDEX_FILE_NAME);
+        let entries = match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|err| {
+                warn!("[Cache index] index '{:?}' is broken, rebuild: {:?}", path, err);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        Self { path, entries, dirty: false, saved_at: Instant::now() }
+    }
+
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&self.entries)?)?;
+        fs::rename(tmp_path, &self.path)?;
+        self.dirty = false;
+        self.saved_at = Instant::now();
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.dirty && self.saved_at.elapsed() >= FLUSH_INTERVAL {
+            return self.save();
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, track_id: &str) -> Option<&CacheEntry> {
+        self.entries.get(track_id)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &CacheEntry> {
+        self.entries.values()
+    }
+
+    pub fn owner_of(&self, file_name: &str) -> Option<&CacheEntry> {
+        self.entries.values().find(|entry| entry.file_name == file_name)
+    }
+
+    pub fn insert(&mut self, entry: CacheEntry) {
+        self.entries.insert(entry.track_id.clone(), entry);
+    }
+
+    pub fn remove(&mut self, track_id: &str) -> Option<CacheEntry> {
+        self.entries.remove(track_id)
+    }
+
+    pub fn touch(&mut self, track_id: &str) {
+        if let Some(entry) = self.entries.get_mut(track_id) {
+            entry.last_played = now();
+            entry.play_count += 1;
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_liked(&mut self, track_id: &str) -> bool {
+        match self.entries.get_mut(track_id) {
+            Some(entry) if !entry.liked => {
+                entry.liked = true;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.entries.values().map(|entry| entry.size).sum()
+    }
+
+    pub fn eviction_candidates(&self, policy: &EvictionPolicy) -> Vec<CacheEntry> {
+        let mut entries: Vec<CacheEntry> = self.entries.values().cloned().collect();
+        match policy {
+            EvictionPolicy::LeastRecentlyUsed => entries.sort_by_key(|entry| (entry.last_played, entry.play_count)),
+            EvictionPolicy::LeastPlayed => entries.sort_by_key(|entry| (entry.play_count, entry.last_played)),
+        }
+        entries
+    }
+
+    pub fn reconcile(&mut self, dir: &Path) {
+        let before = self.entries.len();
+
+        self.entries.retain(|_, entry| match fs::metadata(dir.join(&entry.file_name)) {
+            Ok(metadata) => {
+                entry.size = metadata.len();
+                true
+            },
+            Err(_) => false,
+        });
+        let removed = before - self.entries.len();
+
+        let mut added = 0;
+        for path in Self::audio_files(dir) {
+            let file_name = match path.strip_prefix(dir) {
+                Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+                Err(_) => continue,
+            };
+
+            if self.owner_of(&file_name).is_some() {
+                continue;
+            }
+
+            let mut entry = Self::orphan_entry(&path, &file_name);
+            if self.entries.contains_key(&entry.track_id) {
+                entry.track_id = format!("{ORPHAN_ID_PREFIX}{file_name}");
+            }
+            self.insert(entry);
+            added += 1;
+        }
+
+        info!("[Cache index] reconciled {:?}: {} entries, {} removed, {} added", dir, self.entries.len(), removed, added);
+    }
+
+    pub fn audio_files(dir: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = vec![];
+        let mut dirs = vec![dir.to_path_buf()];
+
+        while let Some(current) = dirs.pop() {
+            let entries = match fs::read_dir(&current) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    warn!("[Cache index] can not read cache directory {:?}: {:?}", current, err);
+                    continue;
+                },
+            };
+
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().is_some_and(|extension| extension == "flac") {
+                    files.push(path);
+                }
+            }
+        }
+
+        files
+    }
+
+    pub fn rename(&mut self, track_id: &str, file_name: &str) {
+        if let Some(entry) = self.entries.get_mut(track_id) {
+            entry.file_name = file_name.to_string();
+        }
+    }
+
+    fn orphan_entry(path: &Path, file_name: &str) -> CacheEntry {
+        let tag = Tag::read_from_path(path).unwrap_or_default();
+        let track_id = match tag.get_vorbis(TIDAL_ID).and_then(|mut values| values.next()) {
+            Some(track_id) if !track_id.is_empty() => track_id.to_string(),
+            _ => format!("{ORPHAN_ID_PREFIX}{file_name}"),
+        };
+        let track = read_track(&tag, &track_id);
+
+        CacheEntry {
+            track_id: track.id,
+            file_name: file_name.to_string(),
+            title: track.title,
+            artist_name: track.artist_name,
+            album_name: track.album_name,
+            album_id: track.metadata.album_id,
+            artist_id: track.metadata.artist_id,
+            disc_number: track.metadata.disc_number,
+            track_number: track.metadata.track_number,
+            duration: track.duration.as_secs(),
+            size: fs::metadata(path).map(|metadata| metadata.len()).unwrap_or_default(),
+            added_at: now(),
+            last_played: 0,
+            play_count: 0,
+            liked: false,
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default()
+}
@@ -0,0 +1,43 @@
+use std::{error::Error, io::Cursor};
+
+use claxon::FlacReader;
+use md5::{Digest, Md5};
+
+// Decodes the whole stream and compares the audio with the STREAMINFO MD5.
+// Encoders may leave the MD5 unset (all zeros), then only the frame CRCs are checked.
+pub fn verify_flac(content: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut reader = FlacReader::new(Cursor::new(content))?;
+    let streaminfo = reader.streaminfo();
+    let bytes_per_sample = streaminfo.bits_per_sample.div_ceil(8) as usize;
+
+    let mut hasher = Md5::new();
+    let mut decoded_samples: u64 = 0;
+    let mut frame_bytes: Vec<u8> = vec![];
+    let mut blocks = reader.blocks();
+    let mut buffer: Vec<i32> = vec![];
+
+    while let Some(block) = blocks.read_next_or_eof(buffer)? {
+        frame_bytes.clear();
+        for sample in 0..block.duration() {
+            for channel in 0..block.channels() {
+                let value = block.sample(channel, sample).to_le_bytes();
+                frame_bytes.extend_from_slice(&value[..bytes_per_sample]);
+            }
+        }
+        hasher.update(&frame_bytes);
+        decoded_samples += block.duration() as u64;
+        buffer = block.into_buffer();
+    }
+
+    if let Some(samples) = streaminfo.samples {
+        if samples != decoded_samples {
+            return Err(format!("truncated audio, decoded {} of {} samples", decoded_samples, samples).into());
+        }
+    }
+
+    if streaminfo.md5sum != [0; 16] && hasher.finalize()[..] != streaminfo.md5sum {
+        return Err("audio MD5 does not match STREAMINFO".into());
+    }
+
+    Ok(())
+}
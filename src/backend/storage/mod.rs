@@ -0,0 +1,632 @@
+use std::{error::Error, fs, io::{Cursor, Read, Write}, sync::{Arc, Mutex}, thread, time::{Duration, Instant}};
+use std::path::{Path, PathBuf};
+use rand::seq::SliceRandom;
+
+use bytes::{Buf, Bytes};
+use log::{info, warn};
+use image::ImageFormat;
+use metaflac::Tag;
+use suppaftp::{types::FileType, FtpStream};
+
+use crate::config::{ExporterFile, ExporterFTP};
+use crate::playlist::{BufferedTrack, Cover, Track};
+use crate::util::lock;
+
+use self::cache_index::{CacheEntry, CacheIndex, EvictionPolicy};
+use self::integrity::verify_flac;
+use self::tagging::{read_track, tag_flac};
+
+pub use self::naming::FileNameTemplate;
+pub use self::tagging::embedded_cover;
+pub use self::s3::S3Storage;
+pub use self::sftp::SftpStorage;
+#[cfg(feature = "smb")]
+pub use self::smb::SmbStorage;
+pub use self::transcoder::Transcoder;
+pub use self::webdav::WebDavStorage;
+
+mod cache_index;
+mod integrity;
+mod naming;
+mod s3;
+mod sftp;
+#[cfg(feature = "smb")]
+mod smb;
+mod tagging;
+mod transcoder;
+mod webdav;
+
+extern crate rand;
+
+pub trait CacheRead {
+    fn read_file(&mut self, track: &Track, output_file_name: &str, output_dir: Option<&str>) -> Result<Option<Bytes>, Box<dyn Error>>;
+}
+
+pub trait CacheRandomRead {
+    fn read_random_files(&mut self, count: usize) -> Result<Vec<BufferedTrack>, Box<dyn Error>>;
+}
+
+pub trait Exporter {
+    fn write_file(&mut self, track: Track, source: Bytes, output_file_name: &str, output_dir: Option<&str>, cover: Option<Vec<u8>>) -> Result<(), Box<dyn Error>> {
+        let content = tag_flac(source, &track, cover)?;
+        self.write_content(&track, content, output_file_name, output_dir)
+    }
+    // Writes already encoded content as is, e.g. transcoded copies.
+    fn write_content(&mut self, track: &Track, content: Bytes, output_file_name: &str, output_dir: Option<&str>) -> Result<(), Box<dyn Error>>;
+    // Extra files next to an exported track, only the local library writes them.
+    fn write_sidecars(&mut self, _track: &Track, _sidecars: &Sidecars) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct Sidecars {
+    pub cover: Option<Bytes>,
+    pub lyrics: Option<String>,
+}
+
+pub fn content_type(file_name: &str) -> &'static str {
+    match file_name.rsplit('.').next().unwrap_or_default() {
+        "flac" => "audio/flac",
+        "opus" => "audio/ogg",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+pub const FILE_STORAGE: &str = "File";
+
+pub trait Storage: Exporter + CacheRead + Send {
+    fn name(&self) -> &str;
+}
+
+const FTP_MAX_BACKOFF: Duration = Duration::from_secs(300);
+const QUARANTINE_DIR: &str = ".quarantine";
+const SIDECAR_COVER_FILE: &str = "cover.jpg";
+
+pub struct FtpStorage {
+    config: ExporterFTP,
+    client: Option<FtpStream>,
+    failures: u32,
+    next_attempt: Option<Instant>,
+}
+
+impl FtpStorage {
+    pub fn init(config: ExporterFTP) -> Self {
+        Self { config, client: None, failures: 0, next_attempt: None }
+    }
+    fn connect_client(config: &ExporterFTP) -> Result<FtpStream, Box<dyn Error>> {
+        let mut client = FtpStream::connect(config.server.as_str())?;
+        client.login(config.username.as_str(), config.password.as_str())?;
+        client.transfer_type(FileType::Binary)?;
+        client.set_mode(suppaftp::Mode::ExtendedPassive);
+        info!("[FTP] connected to {}", config.server);
+        Ok(client)
+    }
+    fn client(&mut self) -> Result<&mut FtpStream, Box<dyn Error>> {
+        if self.client.is_none() {
+            if let Some(next_attempt) = self.next_attempt {
+                if Instant::now() < next_attempt {
+                    return Err(format!("FTP server unavailable, next connection attempt in {:?}", next_attempt - Instant::now()).into());
+                }
+            }
+
+            match Self::connect_client(&self.config) {
+                Ok(client) => {
+                    self.failures = 0;
+                    self.next_attempt = None;
+                    self.client = Some(client);
+                },
+                Err(err) => {
+                    self.failures += 1;
+                    let backoff = Duration::from_secs(2u64.saturating_pow(self.failures)).min(FTP_MAX_BACKOFF);
+                    warn!("[FTP] connection to {} failed ({} times), retry in {:?}: {:?}", self.config.server, self.failures, backoff, err);
+                    self.next_attempt = Some(Instant::now() + backoff);
+                    return Err(err);
+                },
+            }
+        }
+        Ok(self.client.as_mut().unwrap())
+    }
+    fn with_reconnect<T>(&mut self, operation: impl Fn(&mut FtpStream) -> Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+        let result = operation(self.client()?);
+        if result.is_ok() {
+            return result;
+        }
+
+        let alive = self.client.as_mut().is_some_and(|client| client.noop().is_ok());
+        if alive {
+            return result;
+        }
+
+        warn!("[FTP] connection lost, reconnect: {:?}", result.err());
+        self.client = None;
+        operation(self.client()?)
+    }
+    fn file_name(&self, output_file_name: &str, output_dir: Option<&str>) -> String {
+        if let Some(dir) = output_dir {
+            format!("{}{dir}/{output_file_name}", self.config.share)
+        } else { 
+            format!("{}{}", self.config.share, output_file_name)
+        }
+    }
+}
+
+impl Storage for FtpStorage {
+    fn name(&self) -> &str {
+        "FTP"
+    }
+}
+
+impl Exporter for FtpStorage {
+    fn write_content(&mut self, _track: &Track, content: Bytes, output_file_name: &str, output_dir: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let file_name = self.file_name(output_file_name, output_dir);
+
+        let share = self.config.share.clone();
+        self.with_reconnect(|client| {
+            let mut current = share.trim_end_matches('/').to_string();
+            for segment in output_dir.unwrap_or_default().split('/').filter(|segment| !segment.is_empty()) {
+                current = format!("{current}/{segment}");
+                if client.cwd(current.as_str()).is_err() {
+                    client.mkdir(current.as_str())?;
+                }
+            }
+            client.cwd("/")?;
+            client.put_file(file_name.as_str(), &mut content.clone().reader())?;
+            Ok(())
+        })?;
+
+        info!("[Exporter] file saved on ftp: {:?}", file_name);
+        Ok(())
+    }
+}
+
+impl CacheRead for FtpStorage {
+    fn read_file(&mut self, _track: &Track, output_file_name: &str, output_dir: Option<&str>) -> Result<Option<bytes::Bytes>, Box<dyn Error>> {
+        if !self.config.cache_read {
+            return Ok(None);
+        }
+
+        let file_name = self.file_name(output_file_name, output_dir);
+
+        let output = self.with_reconnect(|client| {
+            if client.size(file_name.as_str()).is_err() {
+                return Ok(vec![]);
+            }
+
+            let mut reader = client.retr_as_stream(file_name.as_str())?;
+            let mut output: Vec<u8> = vec![];
+            let read_result = reader.read_to_end(&mut output);
+            client.finalize_retr_stream(reader)?;
+            read_result?;
+
+            Ok(output)
+        })?;
+
+        if output.is_empty() {
+            return Ok(None);
+        }
+
+        info!("[Cache] file readed from ftp: {:?} ({} bytes)", file_name, output.len());
+
+        Ok(Some(output.into()))
+    }
+}
+
+// Read-only view on the FileStorage index shared with the downloader, used to serve the library offline.
+#[derive(Clone)]
+pub struct CacheLibrary {
+    path: PathBuf,
+    index: Arc<Mutex<CacheIndex>>,
+}
+
+impl CacheLibrary {
+    pub fn tracks(&self) -> Vec<Track> {
+        lock(&self.index).entries().map(CacheEntry::to_track).collect()
+    }
+
+    pub fn contains(&self, track_id: &str) -> bool {
+        lock(&self.index).get(track_id).is_some()
+    }
+
+    pub fn track(&self, track_id: &str) -> Option<Track> {
+        lock(&self.index).get(track_id).map(CacheEntry::to_track)
+    }
+
+    pub fn set_liked(&self, track_id: &str) {
+        let mut index = lock(&self.index);
+        if index.set_liked(track_id) {
+            if let Err(err) = index.save() {
+                warn!("[Cache index] save error: {:?}", err);
+            }
+        }
+    }
+
+    // The cover stays embedded in the stream, the downloader renders it through the cover cache.
+    fn read_buffered_track(&self, entry: &CacheEntry) -> Result<BufferedTrack, Box<dyn Error>> {
+        let content = fs::read(self.path.join(&entry.file_name))?;
+        let tag = Tag::read_from(&mut Cursor::new(&content))?;
+
+        Ok(BufferedTrack {
+            track: read_track(&tag, &entry.track_id),
+            stream: Bytes::from(content),
+            cover: Cover::empty(),
+        })
+    }
+}
+
+#[derive(Debug)]
+#[derive(Default)]
+pub struct ScrubReport {
+    pub valid: usize,
+    pub corrupt: usize,
+}
+
+#[derive(Clone)]
+pub struct FileStorage {
+    path: PathBuf,
+    index: Arc<Mutex<CacheIndex>>,
+    max_size: u64,
+    eviction_policy: EvictionPolicy,
+    sidecar_cover: bool,
+    sidecar_lyrics: bool,
+    sidecar_playlist: bool,
+}
+
+impl FileStorage {
+    pub fn init(config: ExporterFile) -> Self {
+        let path = PathBuf::from(config.path);
+        let mut index = CacheIndex::load(&path);
+        index.reconcile(&path);
+        if let Err(err) = index.save() {
+            warn!("[Cache index] save error: {:?}", err);
+        }
+
+        Self {
+            path,
+            index: Arc::new(Mutex::new(index)),
+            max_size: config.max_size_mb * 1024 * 1024,
+            eviction_policy: EvictionPolicy::from_config(&config.eviction),
+            sidecar_cover: config.sidecar_cover,
+            sidecar_lyrics: config.sidecar_lyrics,
+            sidecar_playlist: config.sidecar_playlist,
+        }
+    }
+    fn file_name_with_create_dir(&mut self, relative_path: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let file_name = self.path.join(relative_path);
+        fs::create_dir_all(file_name.parent().unwrap_or(&self.path))?;
+        Ok(file_name)
+    }
+
+    pub fn migrate_layout(&mut self, template: &FileNameTemplate) -> Result<usize, Box<dyn Error>> {
+        let index = self.index.clone();
+        let mut index = lock(&index);
+        let entries: Vec<CacheEntry> = index.entries().cloned().collect();
+        let mut moved = 0;
+
+        for entry in entries {
+            let source = self.path.join(&entry.file_name);
+            let tag = match Tag::read_from_path(&source) {
+                Ok(tag) => tag,
+                Err(err) => {
+                    warn!("[Cache] migrate {:?} skipped, tags unreadable: {:?}", entry.file_name, err);
+                    continue;
+                },
+            };
+
+            let track = read_track(&tag, &entry.track_id);
+            let relative_path = Self::unique_file_name(&index, &track, &template.render(&track).relative_path());
+            if relative_path == entry.file_name {
+                continue;
+            }
+
+            let target = self.file_name_with_create_dir(&relative_path)?;
+            fs::rename(&source, &target)?;
+            if source.with_extension("lrc").exists() {
+                fs::rename(source.with_extension("lrc"), target.with_extension("lrc"))?;
+            }
+            index.rename(&entry.track_id, &relative_path);
+            moved += 1;
+            info!("[Cache] migrated {:?} -> {:?}", entry.file_name, relative_path);
+
+            let mut parent = source.parent();
+            while let Some(dir) = parent.filter(|dir| *dir != self.path.as_path()) {
+                if fs::remove_dir(dir).is_err() {
+                    break;
+                }
+                parent = dir.parent();
+            }
+        }
+
+        index.save()?;
+        Ok(moved)
+    }
+
+    fn unique_file_name(index: &CacheIndex, track: &Track, output_file_name: &str) -> String {
+        match index.owner_of(output_file_name) {
+            Some(entry) if entry.track_id != track.id => {
+                let path = PathBuf::from(output_file_name);
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let extension = path.extension().unwrap_or_default().to_string_lossy();
+                format!("{} [{}].{}", stem, track.id, extension)
+            },
+            _ => output_file_name.to_string(),
+        }
+    }
+
+    fn evict(&self, index: &mut CacheIndex, keep_track_id: &str) {
+        if self.max_size == 0 {
+            return;
+        }
+
+        for entry in index.eviction_candidates(&self.eviction_policy) {
+            if index.total_size() <= self.max_size {
+                break;
+            }
+            if entry.track_id == keep_track_id {
+                continue;
+            }
+
+            match fs::remove_file(self.path.join(&entry.file_name)) {
+                Ok(()) => info!("[Cache] evicted {:?} ({} bytes, played {} times)", entry.file_name, entry.size, entry.play_count),
+                Err(err) => warn!("[Cache] evict {:?} error: {:?}", entry.file_name, err),
+            }
+            let _ = fs::remove_file(self.path.join(&entry.file_name).with_extension("lrc"));
+            index.remove(&entry.track_id);
+        }
+    }
+
+    // Temporary file is a dotfile next to the target, so an interrupted write is never picked up by the index.
+    fn write_atomic(file_name: &Path, content: &[u8]) -> Result<(), Box<dyn Error>> {
+        let tmp_file_name = file_name.with_file_name(format!(".{}.part", file_name.file_name().unwrap_or_default().to_string_lossy()));
+
+        let mut file = fs::File::create(&tmp_file_name)?;
+        let result = file.write_all(content).and_then(|_| file.sync_all());
+        if let Err(err) = result {
+            let _ = fs::remove_file(&tmp_file_name);
+            return Err(err.into());
+        }
+
+        fs::rename(&tmp_file_name, file_name)?;
+        Ok(())
+    }
+
+    fn write_sidecar_cover(&self, dir: &Path, cover: &Bytes) -> Result<(), Box<dyn Error>> {
+        let file_name = dir.join(SIDECAR_COVER_FILE);
+        if file_name.exists() {
+            return Ok(());
+        }
+
+        let content = match image::guess_format(cover)? {
+            ImageFormat::Jpeg => cover.to_vec(),
+            _ => {
+                let mut output = Cursor::new(vec![]);
+                image::load_from_memory(cover)?.to_rgb8().write_to(&mut output, ImageFormat::Jpeg)?;
+                output.into_inner()
+            },
+        };
+        Self::write_atomic(&file_name, &content)?;
+        info!("[Cache] sidecar cover saved {:?}", file_name);
+        Ok(())
+    }
+
+    fn write_sidecar_lyrics(&self, track: &Track, relative_path: &str, lyrics: &str) -> Result<(), Box<dyn Error>> {
+        let file_name = self.path.join(relative_path).with_extension("lrc");
+        let content = format!("[ar:{}]\n[al:{}]\n[ti:{}]\n{}\n", track.artist_name, track.album_name, track.title, lyrics.trim_end());
+        Self::write_atomic(&file_name, content.as_bytes())?;
+        info!("[Cache] sidecar lyrics saved {:?}", file_name);
+        Ok(())
+    }
+
+    // Rewritten on every track added to the album directory, entries follow the disc and track order.
+    fn write_album_playlist(&self, index: &CacheIndex, relative_dir: &Path) -> Result<(), Box<dyn Error>> {
+        let Some(album_name) = relative_dir.file_name() else { return Ok(()) };
+
+        let mut entries: Vec<&CacheEntry> = index.entries()
+            .filter(|entry| Path::new(&entry.file_name).parent() == Some(relative_dir))
+            .collect();
+        entries.sort_by_key(|entry| (entry.disc_number, entry.track_number, entry.file_name.clone()));
+
+        let mut content = String::from("#EXTM3U\n");
+        for entry in entries {
+            let file_name = Path::new(&entry.file_name).file_name().unwrap_or_default().to_string_lossy();
+            content.push_str(&format!("#EXTINF:{},{} - {}\n{}\n", entry.duration, entry.artist_name, entry.title, file_name));
+        }
+
+        let file_name = self.path.join(relative_dir).join(format!("{}.m3u8", album_name.to_string_lossy()));
+        Self::write_atomic(&file_name, content.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn library(&self) -> CacheLibrary {
+        CacheLibrary { path: self.path.clone(), index: self.index.clone() }
+    }
+
+    pub fn scrub(&self) -> ScrubReport {
+        Self::scrub_dir(&self.path, &self.index)
+    }
+
+    fn scrub_dir(path: &Path, index: &Arc<Mutex<CacheIndex>>) -> ScrubReport {
+        let entries: Vec<CacheEntry> = lock(index).entries().cloned().collect();
+        let mut report = ScrubReport::default();
+
+        for entry in entries {
+            match Self::verify_entry(path, index, &entry) {
+                true => report.valid += 1,
+                false => report.corrupt += 1,
+            }
+        }
+
+        report
+    }
+
+    // Corrupt files are moved to the quarantine directory and dropped from the index,
+    // so the next request for the track downloads it again.
+    fn verify_entry(path: &Path, index: &Arc<Mutex<CacheIndex>>, entry: &CacheEntry) -> bool {
+        let verification = fs::read(path.join(&entry.file_name))
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|content| verify_flac(&content));
+
+        let Err(err) = verification else { return true };
+        warn!("[Cache] corrupt file {:?}: {}", entry.file_name, err);
+        match Self::quarantine(path, &entry.file_name) {
+            Ok(()) => info!("[Cache] {:?} quarantined, track {} will be downloaded again", entry.file_name, entry.track_id),
+            Err(err) => warn!("[Cache] quarantine {:?} error: {:?}", entry.file_name, err),
+        }
+
+        let mut index = lock(index);
+        if index.get(&entry.track_id).is_some_and(|current| current.file_name == entry.file_name) {
+            index.remove(&entry.track_id);
+            if let Err(err) = index.save() {
+                warn!("[Cache index] save error: {:?}", err);
+            }
+        }
+        false
+    }
+
+    fn quarantine(path: &Path, relative_path: &str) -> Result<(), Box<dyn Error>> {
+        let source = path.join(relative_path);
+        if !source.exists() {
+            return Ok(());
+        }
+
+        let target = path.join(QUARANTINE_DIR).join(relative_path);
+        fs::create_dir_all(target.parent().unwrap_or(path))?;
+        fs::rename(source, target)?;
+        Ok(())
+    }
+
+    // The whole cache is rechecked every interval, new files are verified before they are indexed.
+    // Only the downloader starts it, the cache commands work on their own FileStorage.
+    pub fn start_scrub(&self, interval: Duration) {
+        let path = self.path.clone();
+        let index = self.index.clone();
+
+        thread::Builder::new()
+            .name("Cache scrub".to_owned())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                let report = Self::scrub_dir(&path, &index);
+                info!("[Cache] background scrub finished, {} valid, {} corrupt", report.valid, report.corrupt);
+            })
+            .unwrap();
+    }
+
+}
+
+impl CacheRead for FileStorage {
+    fn read_file(&mut self, track: &Track, _output_file_name: &str, _output_dir: Option<&str>) -> Result<Option<Bytes>, Box<dyn Error>> {
+        // The index stays unlocked while the file is read, a large FLAC would block other readers and the writer.
+        let file_name = match lock(&self.index).get(&track.id) {
+            Some(entry) => entry.file_name.clone(),
+            None => return Ok(None),
+        };
+
+        let result = fs::read(self.path.join(&file_name));
+        let mut index = lock(&self.index);
+        match result {
+            Ok(file) => {
+                index.touch(&track.id);
+                if let Err(err) = index.flush() {
+                    warn!("[Cache index] save error: {:?}", err);
+                }
+                Ok(Some(bytes::Bytes::from(file)))
+            },
+            Err(_) => {
+                warn!("[Cache] indexed file is missing {:?}, drop entry", file_name);
+                // The track may have been written again meanwhile, only the stale entry is dropped.
+                if index.get(&track.id).is_some_and(|entry| entry.file_name == file_name) {
+                    index.remove(&track.id);
+                    index.save()?;
+                }
+                Ok(None)
+            },
+        }
+    }
+}
+
+impl CacheRandomRead for CacheLibrary {
+    // Weighted sampling without repeats, unreadable or non-FLAC files are skipped.
+    fn read_random_files(&mut self, count: usize) -> Result<Vec<BufferedTrack>, Box<dyn Error>> {
+        let entries: Vec<CacheEntry> = lock(&self.index).entries().cloned().collect();
+        if entries.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let candidates = entries.choose_multiple_weighted(&mut rand::thread_rng(), entries.len(), CacheEntry::cold_start_weight)?;
+        let mut tracks: Vec<BufferedTrack> = vec![];
+
+        for entry in candidates {
+            if tracks.len() >= count {
+                break;
+            }
+
+            match self.read_buffered_track(entry) {
+                Ok(buffered_track) => tracks.push(buffered_track),
+                Err(err) => warn!("[Cache] cold start skips {:?}: {:?}", entry.file_name, err),
+            }
+        }
+
+        Ok(tracks)
+    }
+}
+
+impl Storage for FileStorage {
+    fn name(&self) -> &str {
+        FILE_STORAGE
+    }
+}
+
+impl Exporter for FileStorage {
+    fn write_file(&mut self, track: Track, source: Bytes, output_file_name: &str, output_dir: Option<&str>, cover: Option<Vec<u8>>) -> Result<(), Box<dyn Error>> {
+        // A corrupt download never reaches the index, the exporters run on the export thread, off the download path.
+        let content = tag_flac(source, &track, cover)?;
+        verify_flac(&content)?;
+
+        let index = self.index.clone();
+        let mut index = lock(&index);
+
+        let relative_path = match output_dir {
+            Some(dir) => format!("{dir}/{output_file_name}"),
+            None => output_file_name.to_string(),
+        };
+        let output_file_name = Self::unique_file_name(&index, &track, &relative_path);
+        let file_name = self.file_name_with_create_dir(&output_file_name)?;
+        Self::write_atomic(&file_name, &content)?;
+
+        index.insert(CacheEntry::new(&track, &output_file_name, content.len() as u64));
+        self.evict(&mut index, &track.id);
+        index.save()?;
+        Ok(())
+    }
+
+    fn write_content(&mut self, _track: &Track, content: Bytes, output_file_name: &str, output_dir: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let relative_path = match output_dir {
+            Some(dir) => format!("{dir}/{output_file_name}"),
+            None => output_file_name.to_string(),
+        };
+        let file_name = self.file_name_with_create_dir(&relative_path)?;
+        Self::write_atomic(&file_name, &content)?;
+        Ok(())
+    }
+
+    // Album files (cover, playlist) are only written for layouts with a directory per album.
+    fn write_sidecars(&mut self, track: &Track, sidecars: &Sidecars) -> Result<(), Box<dyn Error>> {
+        let index = self.index.clone();
+        let index = lock(&index);
+        let Some(entry) = index.get(&track.id) else { return Ok(()) };
+        let relative_path = PathBuf::from(&entry.file_name);
+        let album_dir = relative_path.parent().filter(|dir| !dir.as_os_str().is_empty());
+
+        if let (true, Some(lyrics)) = (self.sidecar_lyrics, &sidecars.lyrics) {
+            self.write_sidecar_lyrics(track, &entry.file_name, lyrics)?;
+        }
+        if let (true, Some(cover), Some(dir)) = (self.sidecar_cover, &sidecars.cover, album_dir) {
+            self.write_sidecar_cover(&self.path.join(dir), cover)?;
+        }
+        if let (true, Some(dir)) = (self.sidecar_playlist, album_dir) {
+            self.write_album_playlist(&index, dir)?;
+        }
+        Ok(())
+    }
+}
\ No newline at end of file
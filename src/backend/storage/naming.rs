@@ -0,0 +1,97 @@
+use crate::playlist::Track;
+
+const MAX_SEGMENT_LENGTH: usize = 120;
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct FileNameTemplate {
+    template: String,
+}
+
+pub struct ExportPath {
+    pub output_dir: Option<String>,
+    pub file_name: String,
+}
+
+impl ExportPath {
+    pub fn relative_path(&self) -> String {
+        match &self.output_dir {
+            Some(dir) => format!("{}/{}", dir, self.file_name),
+            None => self.file_name.clone(),
+        }
+    }
+}
+
+impl FileNameTemplate {
+    pub fn new(template: &str) -> Self {
+        Self { template: template.trim().trim_matches('/').to_string() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.template.is_empty()
+    }
+
+    // Placeholders: {artist}, {album_artist}, {album}, {title}, {year}, {disc}, {track}, {id}
+    pub fn render(&self, track: &Track) -> ExportPath {
+        let mut segments: Vec<String> = self.template
+            .split('/')
+            .map(|segment| sanitize_segment(&Self::fill(segment, track)))
+            .collect();
+
+        let file_name = format!("{}.flac", segments.pop().unwrap_or_default());
+        let output_dir = Some(segments.join("/")).filter(|dir| !dir.is_empty());
+
+        ExportPath { output_dir, file_name }
+    }
+
+    fn fill(segment: &str, track: &Track) -> String {
+        let album_artist = match track.metadata.album_artist.as_str() {
+            "" => track.artist_name.as_str(),
+            album_artist => album_artist,
+        };
+
+        segment
+            .replace("{artist}", &track.artist_name)
+            .replace("{album_artist}", album_artist)
+            .replace("{album}", &track.album_name)
+            .replace("{title}", &track.title)
+            .replace("{year}", &track.metadata.year())
+            .replace("{disc}", &track.metadata.disc_number.max(1).to_string())
+            .replace("{track}", &format!("{:0>2}", track.metadata.track_number))
+            .replace("{id}", &track.id)
+    }
+}
+
+// Keeps path segments valid on FAT32/exFAT and SMB shares.
+pub fn sanitize_segment(segment: &str) -> String {
+    let replaced: String = segment.chars()
+        .map(|char| match char {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            char if char.is_control() => '_',
+            char => char,
+        })
+        .collect();
+
+    let mut sanitized = replaced.trim().trim_end_matches('.').trim_end().to_string();
+
+    if sanitized.len() > MAX_SEGMENT_LENGTH {
+        let mut end = MAX_SEGMENT_LENGTH;
+        while !sanitized.is_char_boundary(end) {
+            end -= 1;
+        }
+        sanitized.truncate(end);
+        sanitized = sanitized.trim_end().trim_end_matches('.').to_string();
+    }
+
+    let stem = sanitized.split('.').next().unwrap_or_default().to_uppercase();
+    if RESERVED_NAMES.contains(&stem.as_str()) {
+        sanitized = format!("_{sanitized}");
+    }
+
+    if sanitized.is_empty() { "_".to_string() } else { sanitized }
+}
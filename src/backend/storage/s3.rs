@@ -0,0 +1,227 @@
+use std::{error::Error, time::Duration};
+
+use bytes::Bytes;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{debug, info};
+use reqwest::{blocking::{Client, RequestBuilder}, Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+
+use crate::config::ExporterS3;
+use crate::playlist::Track;
+
+use super::{content_type, CacheRead, Exporter, Storage};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SERVICE: &str = "s3";
+
+pub struct S3Storage {
+    client: Client,
+    config: ExporterS3,
+}
+
+impl S3Storage {
+    pub fn init(config: ExporterS3) -> Result<Self, Box<dyn Error>> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(300))
+            .build()?;
+
+        Ok(Self { client, config })
+    }
+
+    fn object_key(&self, output_file_name: &str, output_dir: Option<&str>) -> String {
+        [self.config.prefix.as_str(), output_dir.unwrap_or_default(), output_file_name].iter()
+            .flat_map(|part| part.split('/'))
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<&str>>()
+            .join("/")
+    }
+
+    fn object_url(&self, key: &str) -> Result<Url, Box<dyn Error>> {
+        let endpoint = Url::parse(&self.config.endpoint)?;
+        let encoded_key = key.split('/').map(uri_encode).collect::<Vec<String>>().join("/");
+        let host = endpoint.host_str().ok_or("S3 endpoint without host")?;
+        let port = endpoint.port().map(|port| format!(":{port}")).unwrap_or_default();
+
+        let url = if self.config.path_style {
+            format!("{}://{}{}/{}/{}", endpoint.scheme(), host, port, uri_encode(&self.config.bucket), encoded_key)
+        } else {
+            format!("{}://{}.{}{}/{}", endpoint.scheme(), self.config.bucket, host, port, encoded_key)
+        };
+
+        Ok(Url::parse(&url)?)
+    }
+
+    fn signed_request(&self, method: Method, url: Url, payload: &[u8]) -> Result<RequestBuilder, Box<dyn Error>> {
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = hex::encode(Sha256::digest(payload));
+
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let headers = [("host", host.as_str()), ("x-amz-content-sha256", payload_hash.as_str()), ("x-amz-date", amz_date.as_str())];
+        let signed_headers = signed_headers(&headers);
+        let canonical_request = canonical_request(method.as_str(), url.path(), &headers, &payload_hash);
+        debug!("[S3] canonical request: {}", canonical_request);
+
+        let scope = credential_scope(&amz_date, &self.config.region, SERVICE);
+        let signature = signature(&self.config.secret_key, &amz_date, &scope, &canonical_request);
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.config.access_key, scope, signed_headers, signature
+        );
+
+        Ok(self.client.request(method, url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("Authorization", authorization))
+    }
+}
+
+impl Storage for S3Storage {
+    fn name(&self) -> &str {
+        "S3"
+    }
+}
+
+impl Exporter for S3Storage {
+    fn write_content(&mut self, _track: &Track, content: Bytes, output_file_name: &str, output_dir: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let key = self.object_key(output_file_name, output_dir);
+
+        self.signed_request(Method::PUT, self.object_url(&key)?, &content)?
+            .header("Content-Type", content_type(output_file_name))
+            .body(content)
+            .send()?
+            .error_for_status()?;
+
+        info!("[Exporter] file saved on s3: {}/{}", self.config.bucket, key);
+        Ok(())
+    }
+}
+
+impl CacheRead for S3Storage {
+    fn read_file(&mut self, _track: &Track, output_file_name: &str, output_dir: Option<&str>) -> Result<Option<Bytes>, Box<dyn Error>> {
+        if !self.config.cache_read {
+            return Ok(None);
+        }
+
+        let key = self.object_key(output_file_name, output_dir);
+        let response = self.signed_request(Method::GET, self.object_url(&key)?, &[])?.send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let output = response.error_for_status()?.bytes()?;
+        if output.is_empty() {
+            return Ok(None);
+        }
+
+        info!("[Cache] file readed from s3: {}/{}", self.config.bucket, key);
+        Ok(Some(output))
+    }
+}
+
+// Headers are lowercase and sorted by name, the query string is always empty.
+fn canonical_request(method: &str, path: &str, headers: &[(&str, &str)], payload_hash: &str) -> String {
+    let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+    format!("{}\n{}\n\n{}\n{}\n{}", method, path, canonical_headers, signed_headers(headers), payload_hash)
+}
+
+fn signed_headers(headers: &[(&str, &str)]) -> String {
+    headers.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(";")
+}
+
+fn credential_scope(amz_date: &str, region: &str, service: &str) -> String {
+    format!("{}/{}/{}/aws4_request", &amz_date[..8], region, service)
+}
+
+// The signing key is derived by chaining HMACs over the parts of the scope: date, region, service and "aws4_request".
+fn signature(secret_key: &str, amz_date: &str, scope: &str, canonical_request: &str) -> String {
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date, scope, hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let signing_key = scope.split('/')
+        .fold(format!("AWS4{}", secret_key).into_bytes(), |key, part| hmac_sha256(&key, part.as_bytes()));
+    hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn uri_encode(segment: &str) -> String {
+    segment.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::{canonical_request, credential_scope, signature};
+
+    // Examples from the AWS Signature Version 4 documentation and test suite.
+    const EMPTY_PAYLOAD_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn empty_payload_hash() {
+        assert_eq!(hex::encode(Sha256::digest(b"")), EMPTY_PAYLOAD_HASH);
+    }
+
+    #[test]
+    fn s3_get_object() {
+        let headers = [
+            ("host", "examplebucket.s3.amazonaws.com"),
+            ("range", "bytes=0-9"),
+            ("x-amz-content-sha256", EMPTY_PAYLOAD_HASH),
+            ("x-amz-date", "20130524T000000Z"),
+        ];
+        let canonical_request = canonical_request("GET", "/test.txt", &headers, EMPTY_PAYLOAD_HASH);
+        assert_eq!(canonical_request, [
+            "GET",
+            "/test.txt",
+            "",
+            "host:examplebucket.s3.amazonaws.com",
+            "range:bytes=0-9",
+            "x-amz-content-sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "x-amz-date:20130524T000000Z",
+            "",
+            "host;range;x-amz-content-sha256;x-amz-date",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        ].join("\n"));
+        assert_eq!(hex::encode(Sha256::digest(canonical_request.as_bytes())), "7344ae5b7ee6c3e7e6b0fe0640412a37625d1fbfff95c48bbb2dc43964946972");
+
+        let scope = credential_scope("20130524T000000Z", "us-east-1", "s3");
+        assert_eq!(scope, "20130524/us-east-1/s3/aws4_request");
+        assert_eq!(
+            signature("wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY", "20130524T000000Z", &scope, &canonical_request),
+            "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+        );
+    }
+
+    #[test]
+    fn get_vanilla() {
+        let headers = [("host", "example.amazonaws.com"), ("x-amz-date", "20150830T123600Z")];
+        let canonical_request = canonical_request("GET", "/", &headers, EMPTY_PAYLOAD_HASH);
+        assert_eq!(hex::encode(Sha256::digest(canonical_request.as_bytes())), "bb579772317eb040ac9ed261061d46c1f17a8133879d6129b6e1c25292927e63");
+
+        let scope = credential_scope("20150830T123600Z", "us-east-1", "service");
+        assert_eq!(
+            signature("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20150830T123600Z", &scope, &canonical_request),
+            "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::{error::Error, io::{Read, Write}, net::{TcpStream, ToSocketAddrs}, path::{Path, PathBuf}, time::Duration};
+
+use bytes::Bytes;
+use log::{info, warn};
+use ssh2::{Session, Sftp};
+
+use crate::config::ExporterSFTP;
+use crate::playlist::Track;
+
+use super::{CacheRead, Exporter, Storage};
+
+// A dead server fails the export instead of blocking the downloader.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct SftpStorage {
+    config: ExporterSFTP,
+    client: Option<Sftp>,
+}
+
+impl SftpStorage {
+    pub fn init(config: ExporterSFTP) -> Self {
+        Self { config, client: None }
+    }
+
+    fn connect_client(config: &ExporterSFTP) -> Result<Sftp, Box<dyn Error>> {
+        let address = config.server.to_socket_addrs()?.next().ok_or(format!("SFTP server not resolved: {}", config.server))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(TcpStream::connect_timeout(&address, TIMEOUT)?);
+        session.set_timeout(TIMEOUT.as_millis() as u32);
+        session.handshake()?;
+
+        if config.private_key.is_empty() {
+            session.userauth_password(&config.username, &config.password)?;
+        } else {
+            let passphrase = Some(config.password.as_str()).filter(|password| !password.is_empty());
+            session.userauth_pubkey_file(&config.username, None, Path::new(&config.private_key), passphrase)?;
+        }
+
+        info!("[SFTP] connected to {}", config.server);
+        Ok(session.sftp()?)
+    }
+
+    fn client(&mut self) -> Result<&Sftp, Box<dyn Error>> {
+        if self.client.is_none() {
+            self.client = Some(Self::connect_client(&self.config)?);
+        }
+        Ok(self.client.as_ref().unwrap())
+    }
+
+    fn file_path(&self, output_file_name: &str, output_dir: Option<&str>) -> PathBuf {
+        let base = PathBuf::from(&self.config.path);
+        match output_dir {
+            Some(dir) => base.join(dir).join(output_file_name),
+            None => base.join(output_file_name),
+        }
+    }
+
+    fn create_dir(&mut self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let client = self.client()?;
+        let mut current = PathBuf::new();
+
+        for component in dir.components() {
+            current.push(component);
+            if client.stat(&current).is_err() {
+                client.mkdir(&current, 0o755)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn with_reconnect<T>(&mut self, operation: impl Fn(&mut Self) -> Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+        match operation(self) {
+            Ok(result) => Ok(result),
+            Err(err) => {
+                warn!("[SFTP] operation failed, reconnect: {:?}", err);
+                self.client = None;
+                operation(self)
+            },
+        }
+    }
+}
+
+impl Storage for SftpStorage {
+    fn name(&self) -> &str {
+        "SFTP"
+    }
+}
+
+impl Exporter for SftpStorage {
+    fn write_content(&mut self, _track: &Track, content: Bytes, output_file_name: &str, output_dir: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let file_path = self.file_path(output_file_name, output_dir);
+
+        self.with_reconnect(|storage| {
+            if let Some(dir) = file_path.parent() {
+                storage.create_dir(dir)?;
+            }
+            let mut file = storage.client()?.create(&file_path)?;
+            file.write_all(&content)?;
+            Ok(())
+        })?;
+
+        info!("[Exporter] file saved on sftp: {:?}", file_path);
+        Ok(())
+    }
+}
+
+impl CacheRead for SftpStorage {
+    fn read_file(&mut self, _track: &Track, output_file_name: &str, output_dir: Option<&str>) -> Result<Option<Bytes>, Box<dyn Error>> {
+        if !self.config.cache_read {
+            return Ok(None);
+        }
+
+        let file_path = self.file_path(output_file_name, output_dir);
+
+        let output = self.with_reconnect(|storage| {
+            let client = storage.client()?;
+            if client.stat(&file_path).is_err() {
+                return Ok(vec![]);
+            }
+
+            let mut output: Vec<u8> = vec![];
+            client.open(&file_path)?.read_to_end(&mut output)?;
+            Ok(output)
+        })?;
+
+        if output.is_empty() {
+            return Ok(None);
+        }
+
+        info!("[Cache] file readed from sftp: {:?}", file_path);
+        Ok(Some(Bytes::from(output)))
+    }
+}
@@ -0,0 +1,114 @@
+use std::{error::Error, io::{Read, Write}};
+
+use bytes::Bytes;
+use log::info;
+use pavao::{SmbClient, SmbCredentials, SmbMode, SmbOpenOptions, SmbOptions};
+
+use crate::config::ExporterSMB;
+use crate::playlist::Track;
+
+use super::{CacheRead, Exporter, Storage};
+
+pub struct SmbStorage {
+    config: ExporterSMB,
+}
+
+impl SmbStorage {
+    pub fn init(config: ExporterSMB) -> Self {
+        Self { config }
+    }
+
+    fn connect_client(&self) -> Result<SmbClient, Box<dyn Error>> {
+        let server = match self.config.server.starts_with("smb://") {
+            true => self.config.server.clone(),
+            false => format!("smb://{}", self.config.server),
+        };
+        let share = match self.config.share.starts_with('/') {
+            true => self.config.share.clone(),
+            false => format!("/{}", self.config.share),
+        };
+
+        let client = SmbClient::new(
+            SmbCredentials::default()
+                .server(server)
+                .share(share)
+                .username(self.config.username.clone())
+                .password(self.config.password.clone())
+                .workgroup(self.config.workgroup.clone()),
+            SmbOptions::default()
+                .case_sensitive(false)
+                .one_share_per_server(true),
+        )?;
+
+        Ok(client)
+    }
+
+    fn file_path(output_file_name: &str, output_dir: Option<&str>) -> String {
+        match output_dir {
+            Some(dir) => format!("/{}/{}", dir.trim_matches('/'), output_file_name),
+            None => format!("/{output_file_name}"),
+        }
+    }
+
+    fn create_dir(client: &SmbClient, output_dir: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let mut current = String::new();
+
+        for segment in output_dir.unwrap_or_default().split('/').filter(|segment| !segment.is_empty()) {
+            current = format!("{current}/{segment}");
+            if client.stat(current.as_str()).is_err() {
+                client.mkdir(current.as_str(), SmbMode::from(0o755))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for SmbStorage {
+    fn name(&self) -> &str {
+        "SMB"
+    }
+}
+
+impl Exporter for SmbStorage {
+    fn write_content(&mut self, _track: &Track, content: Bytes, output_file_name: &str, output_dir: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let client = self.connect_client()?;
+        let file_path = Self::file_path(output_file_name, output_dir);
+        Self::create_dir(&client, output_dir)?;
+
+        let mut file = client.open_with(
+            file_path.as_str(),
+            SmbOpenOptions::default().create(true).write(true).truncate(true),
+        )?;
+        file.write_all(&content)?;
+        file.flush()?;
+
+        info!("[Exporter] file saved on smb: {:?}", file_path);
+        Ok(())
+    }
+}
+
+impl CacheRead for SmbStorage {
+    fn read_file(&mut self, _track: &Track, output_file_name: &str, output_dir: Option<&str>) -> Result<Option<Bytes>, Box<dyn Error>> {
+        if !self.config.cache_read {
+            return Ok(None);
+        }
+
+        let client = self.connect_client()?;
+        let file_path = Self::file_path(output_file_name, output_dir);
+        if client.stat(file_path.as_str()).is_err() {
+            return Ok(None);
+        }
+
+        let mut output: Vec<u8> = vec![];
+        client.open_with(file_path.as_str(), SmbOpenOptions::default().read(true))?
+            .read_to_end(&mut output)?;
+
+        if output.is_empty() {
+            return Ok(None);
+        }
+
+        info!("[Cache] file readed from smb: {:?}", file_path);
+        Ok(Some(Bytes::from(output)))
+    }
+}
@@ -0,0 +1,140 @@
+use std::{error::Error, io::Cursor, time::Duration};
+
+use bytes::Bytes;
+use image::GenericImageView;
+use log::warn;
+use metaflac::block::{Picture, PictureType};
+use metaflac::{Block, BlockType, Tag};
+
+use crate::playlist::{Track, TrackMetadata};
+
+const PADDING_SIZE: u32 = 1024;
+
+pub const TIDAL_ID: &str = "TIDAL_ID";
+
+pub fn tag_flac(source: Bytes, track: &Track, cover: Option<Vec<u8>>) -> Result<Bytes, Box<dyn Error>> {
+    let mut reader = Cursor::new(source);
+    let mut tag = Tag::read_from(&mut reader)?;
+    reader.set_position(0);
+    let frames = Tag::skip_metadata(&mut reader);
+
+    apply_tags(&mut tag, track, cover);
+
+    tag.remove_blocks(BlockType::Padding);
+    tag.push_block(Block::Padding(PADDING_SIZE));
+
+    let mut output: Vec<u8> = Vec::with_capacity(frames.len() + PADDING_SIZE as usize);
+    tag.write_to(&mut output)?;
+    output.extend_from_slice(&frames);
+
+    Ok(Bytes::from(output))
+}
+
+fn apply_tags(tag: &mut Tag, track: &Track, cover: Option<Vec<u8>>) {
+    let metadata = &track.metadata;
+    let vorbis = tag.vorbis_comments_mut();
+    vorbis.set_title(vec![track.title.clone()]);
+    vorbis.set_album(vec![track.album_name.clone()]);
+    vorbis.set_artist(vec![track.artist_name.clone()]);
+
+    let mut set = |key: &str, value: String| {
+        if !value.is_empty() {
+            vorbis.set(key, vec![value]);
+        }
+    };
+    let number = |value: u32| if value > 0 { value.to_string() } else { String::new() };
+
+    set("ALBUMARTIST", metadata.album_artist.clone());
+    set("TRACKNUMBER", number(metadata.track_number));
+    set("TRACKTOTAL", number(metadata.total_tracks));
+    set("TOTALTRACKS", number(metadata.total_tracks));
+    set("DISCNUMBER", number(metadata.disc_number));
+    set("DISCTOTAL", number(metadata.total_discs));
+    set("TOTALDISCS", number(metadata.total_discs));
+    set("DATE", metadata.release_date.clone());
+    set("YEAR", metadata.year());
+    set("ISRC", metadata.isrc.clone());
+    set("COPYRIGHT", metadata.copyright.clone());
+    set("ITUNESADVISORY", metadata.explicit.map(|explicit| if explicit { "1" } else { "0" }.to_string()).unwrap_or_default());
+    set("TIDAL_AUDIO_QUALITY", metadata.audio_quality.clone());
+    set("REPLAYGAIN_TRACK_GAIN", metadata.replay_gain.map(|gain| format!("{gain:.2} dB")).unwrap_or_default());
+    set("REPLAYGAIN_TRACK_PEAK", metadata.peak.map(|peak| format!("{peak:.6}")).unwrap_or_default());
+    if !track.id.is_empty() {
+        set(TIDAL_ID, track.id.clone());
+    }
+    set("TIDAL_ALBUM_ID", metadata.album_id.clone());
+    set("TIDAL_ARTIST_ID", metadata.artist_id.clone());
+
+    if let Some(cover) = cover {
+        match cover_picture(cover) {
+            Ok(picture) => {
+                tag.remove_picture_type(PictureType::CoverFront);
+                tag.push_block(Block::Picture(picture));
+            },
+            Err(err) => warn!("[Tagging] cover skipped, unsupported image: {:?}", err),
+        }
+    }
+}
+
+pub(super) fn cover_picture(data: Vec<u8>) -> Result<Picture, Box<dyn Error>> {
+    let format = image::guess_format(&data)?;
+    let image = image::load_from_memory_with_format(&data, format)?;
+    let (width, height) = image.dimensions();
+
+    let mut picture = Picture::new();
+    picture.picture_type = PictureType::CoverFront;
+    picture.mime_type = format.to_mime_type().to_string();
+    picture.width = width;
+    picture.height = height;
+    picture.depth = image.color().bits_per_pixel() as u32;
+    picture.data = data;
+
+    Ok(picture)
+}
+
+pub fn embedded_cover(content: &[u8]) -> Option<Bytes> {
+    let tag = Tag::read_from(&mut Cursor::new(content)).ok()?;
+    let picture = tag.pictures().find(|picture| picture.picture_type == PictureType::CoverFront)?;
+    Some(Bytes::from(picture.data.clone()))
+}
+
+pub fn read_track(tag: &Tag, track_id: &str) -> Track {
+    let first = |key: &str| tag.get_vorbis(key)
+        .and_then(|mut values| values.next())
+        .unwrap_or_default()
+        .to_string();
+    let number = |key: &str| first(key).split('/').next().unwrap_or_default().parse().unwrap_or_default();
+    let gain = |key: &str| first(key).trim_end_matches("dB").trim().parse().ok();
+
+    Track {
+        id: track_id.to_string(),
+        title: first("TITLE"),
+        artist_name: first("ARTIST"),
+        album_name: first("ALBUM"),
+        album_image: "".to_string(),
+        duration: tag.get_streaminfo()
+            .filter(|streaminfo| streaminfo.sample_rate > 0)
+            .map(|streaminfo| Duration::from_secs_f64(streaminfo.total_samples as f64 / streaminfo.sample_rate as f64))
+            .unwrap_or_default(),
+        metadata: TrackMetadata {
+            album_id: first("TIDAL_ALBUM_ID"),
+            artist_id: first("TIDAL_ARTIST_ID"),
+            album_artist: first("ALBUMARTIST"),
+            track_number: number("TRACKNUMBER"),
+            total_tracks: number("TRACKTOTAL"),
+            disc_number: number("DISCNUMBER"),
+            total_discs: number("DISCTOTAL"),
+            release_date: first("DATE"),
+            isrc: first("ISRC"),
+            copyright: first("COPYRIGHT"),
+            explicit: match first("ITUNESADVISORY").as_str() {
+                "1" => Some(true),
+                "0" => Some(false),
+                _ => None,
+            },
+            audio_quality: first("TIDAL_AUDIO_QUALITY"),
+            replay_gain: gain("REPLAYGAIN_TRACK_GAIN"),
+            peak: gain("REPLAYGAIN_TRACK_PEAK"),
+        },
+    }
+}
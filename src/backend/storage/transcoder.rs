@@ -0,0 +1,171 @@
+use std::{error::Error, fs, io::Cursor, path::Path, process::Command, thread};
+
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use bytes::Bytes;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use log::{error, info};
+use metaflac::Tag;
+
+use crate::config;
+use crate::playlist::Track;
+
+use super::tagging::{cover_picture, tag_flac};
+use super::Storage;
+
+// Jobs hold the whole FLAC, a full queue blocks the downloader until ffmpeg catches up.
+const QUEUE_SIZE: usize = 4;
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+enum Format {
+    Opus,
+    Mp3,
+}
+
+impl Format {
+    fn parse(format: &str) -> Result<Self, Box<dyn Error>> {
+        match format {
+            "opus" => Ok(Format::Opus),
+            "mp3" => Ok(Format::Mp3),
+            _ => Err(format!("unsupported format '{}', use opus or mp3", format).into()),
+        }
+    }
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Opus => "opus",
+            Format::Mp3 => "mp3",
+        }
+    }
+}
+
+struct TranscodeJob {
+    track: Track,
+    source: Bytes,
+    file_name: String,
+    output_dir: Option<String>,
+    cover: Option<Vec<u8>>,
+}
+
+// Lossy copies are encoded by ffmpeg on a background thread which owns the target storages,
+// the downloader only queues the job.
+#[derive(Clone)]
+pub struct Transcoder {
+    sender: Sender<TranscodeJob>,
+}
+
+impl Transcoder {
+    pub fn start(config: config::Transcoder, storages: Vec<Box<dyn Storage>>) -> Result<Self, Box<dyn Error>> {
+        let format = Format::parse(&config.format)?;
+        let (sender, receiver) = bounded(QUEUE_SIZE);
+
+        info!("[Transcoder] {:?} {}kbps for: {}", format, config.bitrate_kbps, storages.iter().map(|storage| storage.name()).collect::<Vec<&str>>().join(", "));
+        thread::spawn(move || Self::worker(config, format, storages, receiver));
+
+        Ok(Self { sender })
+    }
+
+    pub fn enqueue(&self, track: Track, source: Bytes, file_name: &str, output_dir: Option<&str>, cover: Option<Vec<u8>>) {
+        let job = TranscodeJob {
+            track,
+            source,
+            file_name: file_name.to_string(),
+            output_dir: output_dir.map(str::to_string),
+            cover,
+        };
+        if self.sender.send(job).is_err() {
+            error!("[Transcoder] queue closed, job dropped");
+        }
+    }
+
+    fn worker(config: config::Transcoder, format: Format, mut storages: Vec<Box<dyn Storage>>, receiver: Receiver<TranscodeJob>) {
+        for job in receiver {
+            let content = match transcode(&config, format, &job) {
+                Ok(content) => content,
+                Err(err) => {
+                    error!("[Transcoder] transcode error, track: {:?}, error: {:?}", job.track, err);
+                    continue;
+                },
+            };
+
+            let file_name = Path::new(&job.file_name).with_extension(format.extension()).to_string_lossy().to_string();
+            for storage in storages.iter_mut() {
+                match storage.write_content(&job.track, content.clone(), &file_name, job.output_dir.as_deref()) {
+                    Ok(()) => info!("[Storage {}] transcoded file wrote, track: {:?}", storage.name(), job.track),
+                    Err(err) => error!("[Storage {}] transcoded file wrote error, track: {:?}, error: {:?}", storage.name(), job.track, err),
+                }
+            }
+        }
+    }
+}
+
+fn transcode(config: &config::Transcoder, format: Format, job: &TranscodeJob) -> Result<Bytes, Box<dyn Error>> {
+    let workdir = tempfile::tempdir()?;
+    let input = workdir.path().join("input.flac");
+    let output = workdir.path().join(format!("output.{}", format.extension()));
+
+    // The cover is passed separately, ffmpeg does not carry FLAC pictures over to either format.
+    let tagged = tag_flac(job.source.clone(), &job.track, None)?;
+    fs::write(&input, &tagged)?;
+
+    let mut command = Command::new(&config.ffmpeg);
+    command.args(["-hide_banner", "-loglevel", "error", "-y", "-i"]).arg(&input);
+
+    match format {
+        Format::Opus => {
+            // Opus keeps the cover as a METADATA_BLOCK_PICTURE comment, too long for the command line.
+            let metadata = workdir.path().join("metadata.txt");
+            fs::write(&metadata, ffmetadata(&tagged, job.cover.clone())?)?;
+            command.arg("-i").arg(&metadata)
+                .args(["-map", "0:a", "-map_metadata", "1", "-c:a", "libopus"]);
+        },
+        Format::Mp3 => {
+            match &job.cover {
+                Some(cover) => {
+                    let cover_file = workdir.path().join("cover");
+                    fs::write(&cover_file, cover)?;
+                    command.arg("-i").arg(&cover_file)
+                        .args(["-map", "0:a", "-map", "1:v", "-c:v", "copy", "-disposition:v:0", "attached_pic"]);
+                },
+                None => { command.args(["-map", "0:a"]); },
+            }
+            command.args(["-map_metadata", "0", "-c:a", "libmp3lame", "-id3v2_version", "3"]);
+        },
+    }
+
+    let result = command.arg("-b:a").arg(format!("{}k", config.bitrate_kbps)).arg(&output).output()?;
+    if !result.status.success() {
+        return Err(format!("ffmpeg failed ({}): {}", result.status, String::from_utf8_lossy(&result.stderr).trim()).into());
+    }
+
+    Ok(Bytes::from(fs::read(&output)?))
+}
+
+fn ffmetadata(tagged: &[u8], cover: Option<Vec<u8>>) -> Result<String, Box<dyn Error>> {
+    let tag = Tag::read_from(&mut Cursor::new(tagged))?;
+    let mut metadata = String::from(";FFMETADATA1\n");
+
+    for (key, values) in tag.vorbis_comments().map(|vorbis| &vorbis.comments).into_iter().flatten() {
+        for value in values {
+            metadata.push_str(&format!("{}={}\n", escape_ffmetadata(key), escape_ffmetadata(value)));
+        }
+    }
+
+    if let Some(picture) = cover.map(cover_picture).transpose()? {
+        let block = BASE64_STANDARD.encode(picture.to_bytes());
+        metadata.push_str(&format!("METADATA_BLOCK_PICTURE={}\n", escape_ffmetadata(&block)));
+    }
+
+    Ok(metadata)
+}
+
+fn escape_ffmetadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        if matches!(character, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
@@ -0,0 +1,124 @@
+use std::{error::Error, time::Duration};
+
+use bytes::Bytes;
+use log::{debug, info};
+use reqwest::{blocking::Client, Method, StatusCode, Url};
+
+use crate::config::ExporterWebDAV;
+use crate::playlist::Track;
+
+use super::{content_type, CacheRead, Exporter, Storage};
+
+pub struct WebDavStorage {
+    client: Client,
+    url: String,
+    username: String,
+    password: String,
+    cache_read: bool,
+}
+
+impl WebDavStorage {
+    pub fn init(config: ExporterWebDAV) -> Result<Self, Box<dyn Error>> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(300))
+            .build()?;
+
+        Ok(Self {
+            client,
+            url: config.url,
+            username: config.username,
+            password: config.password,
+            cache_read: config.cache_read,
+        })
+    }
+
+    fn build_url(&self, segments: &[&str]) -> Result<Url, Box<dyn Error>> {
+        let mut url = Url::parse(&self.url)?;
+        url.path_segments_mut()
+            .map_err(|_| format!("WebDAV url can not be a base: {}", self.url))?
+            .pop_if_empty()
+            .extend(segments.iter().filter(|segment| !segment.is_empty()));
+        Ok(url)
+    }
+
+    fn dir_segments(output_dir: Option<&str>) -> Vec<&str> {
+        output_dir.map(|dir| dir.split('/').collect()).unwrap_or_default()
+    }
+
+    fn request(&self, method: Method, url: Url) -> reqwest::blocking::RequestBuilder {
+        let request = self.client.request(method, url);
+        if self.username.is_empty() {
+            request
+        } else {
+            request.basic_auth(&self.username, Some(&self.password))
+        }
+    }
+
+    fn create_dir(&self, output_dir: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let segments = Self::dir_segments(output_dir);
+
+        for depth in 1..=segments.len() {
+            let mut url = self.build_url(&segments[..depth])?;
+            url.path_segments_mut().map_err(|_| "WebDAV url can not be a base")?.push("");
+
+            let response = self.request(Method::from_bytes(b"MKCOL")?, url.clone()).send()?;
+            match response.status() {
+                status if status.is_success() => debug!("[WebDAV] collection created: {}", url),
+                StatusCode::METHOD_NOT_ALLOWED => debug!("[WebDAV] collection exists: {}", url),
+                status => return Err(format!("WebDAV MKCOL {} failed (status: {})", url, status).into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for WebDavStorage {
+    fn name(&self) -> &str {
+        "WebDAV"
+    }
+}
+
+impl Exporter for WebDavStorage {
+    fn write_content(&mut self, _track: &Track, content: Bytes, output_file_name: &str, output_dir: Option<&str>) -> Result<(), Box<dyn Error>> {
+        self.create_dir(output_dir)?;
+
+        let mut segments = Self::dir_segments(output_dir);
+        segments.push(output_file_name);
+        let url = self.build_url(&segments)?;
+
+        self.request(Method::PUT, url.clone())
+            .header("Content-Type", content_type(output_file_name))
+            .body(content)
+            .send()?
+            .error_for_status()?;
+
+        info!("[Exporter] file saved on webdav: {}", url);
+        Ok(())
+    }
+}
+
+impl CacheRead for WebDavStorage {
+    fn read_file(&mut self, _track: &Track, output_file_name: &str, output_dir: Option<&str>) -> Result<Option<Bytes>, Box<dyn Error>> {
+        if !self.cache_read {
+            return Ok(None);
+        }
+
+        let mut segments = Self::dir_segments(output_dir);
+        segments.push(output_file_name);
+        let url = self.build_url(&segments)?;
+
+        let response = self.request(Method::GET, url.clone()).send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let output = response.error_for_status()?.bytes()?;
+        if output.is_empty() {
+            return Ok(None);
+        }
+
+        info!("[Cache] file readed from webdav: {}", url);
+        Ok(Some(output))
+    }
+}
@@ -1,82 +1,203 @@
-use std::{error::Error, time::Duration};
+use std::{error::Error, sync::{Arc, Mutex}, thread, time::Duration};
 use bytes::Bytes;
-use log::info;
+use log::{info, warn};
 use rand::{thread_rng, Rng};
 use rand::seq::SliceRandom;
 use serde_json::Value;
 
-use crate::{config::Config, state::PlayerBus, playlist::Track};
+use crate::{config::Config, state::{Message, PlayerBus}, playlist::{Track, TrackMetadata}};
+use crate::util::lock;
 use self::session::Session;
-use super::Backend;
+use super::{error::BackendError, Backend};
 
 mod session;
 
+const CONNECTION_ATTEMPTS: u32 = 5;
+const CONNECTION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const COVER_SIZES: [u32; 5] = [80, 160, 320, 640, 1280];
+
 #[derive(Clone)]
 pub struct TidalBackend {
-    session: Session,
+    session: Arc<Mutex<Option<Session>>>,
 }
 
 impl Backend for TidalBackend {
     fn init(config: &mut Config, player_bus: PlayerBus) -> Self {
-        let session = Session::setup(config, player_bus.clone());
-        Self {
-            session: session.clone(),
+        let backend = Self {
+            session: Arc::new(Mutex::new(None)),
+        };
+
+        if Session::wait_for_internet_connection(CONNECTION_ATTEMPTS) {
+            *lock(&backend.session) = Some(Session::setup(config, player_bus.clone()));
+        } else {
+            warn!("[Tidal] unreachable, start in offline mode");
+            player_bus.publish_message(Message::TidalBackendUnreachable);
         }
+
+        backend.watch_connection(config.clone(), player_bus);
+        backend
     }
-    fn discovery(&self, discovery_fn: impl Fn(Track)) {
-        let _ = self.discover_mixes(&self.session, &discovery_fn);
-        let _ = self.discover_favorities_tracks(&self.session, &discovery_fn);
+    // Mixes and favorites are independent, a failure of one does not stop the other.
+    fn discovery(&self, discovery_fn: impl Fn(Track)) -> Result<(), BackendError> {
+        let session = self.session()?;
+        let mixes = self.discover_mixes(&session, &discovery_fn);
+        let favorites = self.discover_favorities_tracks(&session, &discovery_fn);
+        mixes.and(favorites)
     }
-    fn get_track(&mut self, track_id: String) -> Result<Bytes, Box<dyn Error>> {
+    fn get_track(&mut self, track_id: String) -> Result<Bytes, BackendError> {
+        let mut session = self.session()?;
+        let mut last_error: Option<Box<dyn Error>> = None;
+
         for _ in 1..5 {
             TidalBackend::sleep_for_health();
 
-            match self.session.get_track_bytes(track_id.clone()) {
+            match session.get_track_bytes(track_id.clone()) {
                 Ok(file) => return Ok(file),
-                Err(_) => continue,
+                Err(err) => last_error = Some(err),
             }
         }
-    
-        Err("Track Download fail!".into())
+
+        Err(BackendError::Request(last_error.unwrap_or_else(|| "track download failed".into())))
     }
-    fn get_cover(&self, cover_url: String) -> Result<Bytes, Box<dyn Error>> {
-        self.session.get_cover_bytes(cover_url)
+    fn get_cover(&self, cover_url: String) -> Result<Bytes, BackendError> {
+        Ok(self.session()?.get_cover_bytes(cover_url)?)
     }
-    fn discovery_radio(&self, track_id: &str, discovery_fn: impl Fn(Vec<Track>)) {
-        self.discovery_track(track_id, discovery_fn);
+    fn discovery_radio(&self, track_id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+        self.discovery_track(track_id, discovery_fn)
     }
-    fn discovery_track(&self, track_id: &str, discovery_fn: impl Fn(Vec<Track>)) {
+    fn discovery_track(&self, track_id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+        let session = self.session()?;
         info!("[Discovery] Discover radio for track: {}", track_id);
         TidalBackend::sleep_for_health();
-        let radio = self.session.get_track_radio(track_id).unwrap();
-        let tracks = Self::parse_tracks(&radio["items"]);
+        let radio = session.get_track_radio(track_id)?;
+        let tracks = Self::parse_tracks(&radio["items"])?;
 
         info!("[Discovery] Discover tracks: {:?}", tracks);
         discovery_fn(tracks);
+        Ok(())
     }
-    fn discovery_album(&self, album_id: &str, discovery_fn: impl Fn(Vec<Track>)) {
+    fn discovery_album(&self, album_id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+        let session = self.session()?;
         TidalBackend::sleep_for_health();
-        let album = self.session.get_album(album_id).unwrap();
-        let tracks = Self::parse_tracks(&album["items"]);
+        let album = session.get_album(album_id)?;
+        let tracks = Self::parse_tracks(&album["items"])?;
 
         info!("[Discovery] Discover tracks {:?} from album: {}", tracks, album_id);
         discovery_fn(tracks);
+        Ok(())
     }
-    fn discovery_artist(&self, artist_id: &str, discovery_fn: impl Fn(Vec<Track>)) {
+    fn discovery_artist(&self, artist_id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+        let session = self.session()?;
         TidalBackend::sleep_for_health();
-        let artist = self.session.get_artist(artist_id).unwrap();
-        let tracks = Self::parse_tracks(&artist["items"]);
+        let artist = session.get_artist(artist_id)?;
+        let tracks = Self::parse_tracks(&artist["items"])?;
 
         info!("[Discovery] Discover tracks {:?} from artist: {}", tracks, artist_id);
         discovery_fn(tracks);
+        Ok(())
     }
-    fn add_track_to_favorites(&self, track_id: &str) {
-        let _ = self.session.add_track_to_favorites(track_id);
+    fn add_track_to_favorites(&self, track_id: &str) -> Result<(), BackendError> {
+        self.session()?.add_track_to_favorites(track_id)
     }
 }
 
 impl TidalBackend {
-    fn discover_favorities_tracks(&self, session: &Session, discovery_fn: impl Fn(Track)) -> Result<(), Box<dyn Error>> {
+    fn session(&self) -> Result<Session, BackendError> {
+        lock(&self.session).clone().ok_or_else(|| BackendError::Unavailable("no Tidal session, offline".to_string()))
+    }
+
+    // Creates the session once Tidal is reachable again and reports connectivity changes to the state.
+    fn watch_connection(&self, mut config: Config, player_bus: PlayerBus) {
+        let session = self.session.clone();
+        let mut reachable = lock(&session).is_some();
+
+        thread::spawn(move || loop {
+            thread::sleep(CONNECTION_CHECK_INTERVAL);
+
+            let now_reachable = Session::is_reachable();
+            if now_reachable && lock(&session).is_none() {
+                let new_session = Session::setup(&mut config, player_bus.clone());
+                *lock(&session) = Some(new_session);
+            }
+
+            if now_reachable != reachable {
+                reachable = now_reachable;
+                info!("[Tidal] connection changed, reachable: {}", reachable);
+                player_bus.publish_message(if reachable { Message::TidalBackendReachable } else { Message::TidalBackendUnreachable });
+            }
+        });
+    }
+
+    pub fn get_track_with_rate_limit(&self, track_id: String, bytes_per_second: u64) -> Result<Bytes, Box<dyn Error>> {
+        if bytes_per_second == 0 {
+            return Ok(self.clone().get_track(track_id)?);
+        }
+
+        TidalBackend::sleep_for_health();
+        self.session()?.get_track_bytes_with_rate_limit(track_id, bytes_per_second)
+    }
+
+    pub fn favorite_tracks(&self) -> Result<Vec<Track>, Box<dyn Error>> {
+        let items = self.session()?.get_all_favorites()?;
+        Ok(Self::parse_tracks(&Value::Array(items.iter().map(|item| item["item"].clone()).collect()))?)
+    }
+
+    pub fn album_tracks(&self, album_id: &str) -> Result<Vec<Track>, Box<dyn Error>> {
+        Ok(Self::parse_tracks(&self.session()?.get_album(album_id)?["items"])?)
+    }
+
+    pub fn artist_tracks(&self, artist_id: &str) -> Result<Vec<Track>, Box<dyn Error>> {
+        Ok(Self::parse_tracks(&self.session()?.get_artist(artist_id)?["items"])?)
+    }
+
+    pub fn playlist_tracks(&self, playlist_id: &str) -> Result<Vec<Track>, Box<dyn Error>> {
+        Ok(Self::parse_tracks(&Value::Array(self.session()?.get_all_playlist_tracks(playlist_id)?))?)
+    }
+
+    // Synced lyrics in LRC format, tracks without lyrics or with plain text only return None.
+    pub fn lyrics(&self, track_id: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let lyrics = self.session()?.get_lyrics(track_id)?;
+        Ok(lyrics
+            .and_then(|lyrics| lyrics["subtitles"].as_str().map(str::to_string))
+            .filter(|subtitles| !subtitles.trim().is_empty()))
+    }
+
+    // Smallest square size Tidal serves that is not upscaled on screen.
+    pub fn cover_size_for(pixels: u32) -> u32 {
+        COVER_SIZES.into_iter().find(|size| *size >= pixels).unwrap_or(COVER_SIZES[COVER_SIZES.len() - 1])
+    }
+
+    pub fn cover_url(album_image: &str, size: u32) -> String {
+        match album_image.rsplit_once('/') {
+            Some((base, _)) => format!("{}/{}x{}.jpg", base, size, size),
+            None => album_image.to_string(),
+        }
+    }
+
+    pub fn complete_metadata(&self, track: &mut Track) -> Result<(), Box<dyn Error>> {
+        if track.metadata.album_id.is_empty() {
+            return Ok(());
+        }
+
+        let album = self.session()?.get_album_details(&track.metadata.album_id)?;
+        let metadata = &mut track.metadata;
+
+        metadata.total_tracks = album["numberOfTracks"].as_u64().unwrap_or_default() as u32;
+        metadata.total_discs = album["numberOfVolumes"].as_u64().unwrap_or_default() as u32;
+        if let Some(release_date) = album["releaseDate"].as_str() {
+            metadata.release_date = release_date.to_string();
+        }
+        if let Some(album_artist) = album["artist"]["name"].as_str() {
+            metadata.album_artist = album_artist.to_string();
+        }
+        if metadata.copyright.is_empty() {
+            metadata.copyright = album["copyright"].as_str().unwrap_or_default().to_string();
+        }
+
+        Ok(())
+    }
+
+    fn discover_favorities_tracks(&self, session: &Session, discovery_fn: impl Fn(Track)) -> Result<(), BackendError> {
         let v = session.get_favorites()?;
 
         if let Value::Array(items) = &v["items"] {
@@ -86,7 +207,9 @@ impl TidalBackend {
             
             for item in shuffled_items {
                 if item["item"]["adSupportedStreamReady"].as_bool().is_some_and(|ready| ready) {
-                    discovery_fn(Track::build_from_json(item["item"].clone()));
+                    if let Some(track) = Track::build_from_json(item["item"].clone()) {
+                        discovery_fn(track);
+                    }
                 }
             }
         }
@@ -94,37 +217,40 @@ impl TidalBackend {
         Ok(())
     }
 
-    fn discover_mixes(&self, session: &Session, discovery_fn: impl Fn(Track)) -> Result<(), Box<dyn Error>> {
+    // A mix that can not be loaded is skipped, the others still fill the playlist.
+    fn discover_mixes(&self, session: &Session, discovery_fn: impl Fn(Track)) -> Result<(), BackendError> {
         let v = session.get_page_for_you()?;
         let mixes = parse_modules(v)?;
 
         for track in &shuffle_vec(
             shuffle_vec(mixes).iter()
                 .filter(|mix| mix["mixType"].is_string())
-                .map(|mix| session.get_mix(mix["id"].as_str().unwrap()).unwrap())
-                .map(|mix| parse_modules(mix).unwrap())
-                .flat_map(|mix_tracks| shuffle_vec(mix_tracks.clone()))
+                .filter_map(|mix| mix["id"].as_str())
+                .filter_map(|mix_id| match session.get_mix(mix_id).map_err(BackendError::from).and_then(parse_modules) {
+                    Ok(mix_tracks) => Some(mix_tracks),
+                    Err(err) => { warn!("[Tidal] mix {} skipped: {}", mix_id, err); None },
+                })
+                .flat_map(shuffle_vec)
                 .filter(|mix_track| mix_track["adSupportedStreamReady"].as_bool().is_some_and(|ready| ready))
                 .collect()
         ) {
-                discovery_fn(Track::build_from_json(track.clone()));
+                if let Some(track) = Track::build_from_json(track.clone()) {
+                    discovery_fn(track);
+                }
             }
 
         Ok(())
     }
 
-    fn parse_tracks(items: &Value) -> Vec<Track> {
-        let mut tracks: Vec<Track> = vec![];
-
-        if let Value::Array(items) = items {
-            for item in items {
-                if item["adSupportedStreamReady"].as_bool().is_some_and(|ready| ready) {
-                    tracks.push(Track::build_from_json(item.to_owned()));
-                }
-            }
-        }
+    fn parse_tracks(items: &Value) -> Result<Vec<Track>, BackendError> {
+        let Value::Array(items) = items else {
+            return Err(BackendError::InvalidResponse("tracks list expected".to_string()));
+        };
 
-        tracks
+        Ok(items.iter()
+            .filter(|item| item["adSupportedStreamReady"].as_bool().is_some_and(|ready| ready))
+            .filter_map(|item| Track::build_from_json(item.to_owned()))
+            .collect())
     }
 
     fn sleep_for_health() {
@@ -134,28 +260,47 @@ impl TidalBackend {
 }
 
 impl Track {
-    pub fn build_from_json(item: Value) -> Track {
-        let cover = if item["album"]["cover"].is_string() { 
-            item["album"]["cover"].as_str().unwrap()
-        } else { 
-            "0dfd3368-3aa1-49a3-935f-10ffb39803c0" 
-        }.replace('-', "/");
+    // Items without an id can not be played and are left out.
+    pub fn build_from_json(item: Value) -> Option<Track> {
+        let id = item["id"].as_i64()?.to_string();
+        let cover = item["album"]["cover"].as_str()
+            .unwrap_or("0dfd3368-3aa1-49a3-935f-10ffb39803c0")
+            .replace('-', "/");
 
         let artist_name = item["artists"].as_array()
             .unwrap_or(&Vec::new())
             .iter()
-            .map(|item| item["name"].as_str().unwrap())
+            .filter_map(|item| item["name"].as_str())
             .collect::<Vec<&str>>()
             .join(", ");
 
-        Track {
-            id: item["id"].as_i64().unwrap().to_string(),
+        Some(Track {
+            id,
             title: item["title"].as_str().unwrap_or_default().to_string(),
             artist_name,
             album_name: item["album"]["title"].as_str().unwrap_or_default().to_string(),
             album_image: format!("https://resources.tidal.com/images/{}/{}x{}.jpg", cover, 320, 320),
             duration: Duration::from_secs(item["duration"].as_u64().unwrap_or_default()),
-        }
+            metadata: TrackMetadata {
+                album_id: item["album"]["id"].as_i64().map(|id| id.to_string()).unwrap_or_default(),
+                artist_id: item["artist"]["id"].as_i64().map(|id| id.to_string()).unwrap_or_default(),
+                album_artist: item["artist"]["name"].as_str().unwrap_or_default().to_string(),
+                track_number: item["trackNumber"].as_u64().unwrap_or_default() as u32,
+                total_tracks: 0,
+                disc_number: item["volumeNumber"].as_u64().unwrap_or_default() as u32,
+                total_discs: 0,
+                release_date: item["album"]["releaseDate"].as_str()
+                    .or(item["streamStartDate"].as_str())
+                    .unwrap_or_default()
+                    .chars().take(10).collect(),
+                isrc: item["isrc"].as_str().unwrap_or_default().to_string(),
+                copyright: item["copyright"].as_str().unwrap_or_default().to_string(),
+                explicit: item["explicit"].as_bool(),
+                audio_quality: item["audioQuality"].as_str().unwrap_or_default().to_string(),
+                replay_gain: item["replayGain"].as_f64(),
+                peak: item["peak"].as_f64(),
+            },
+        })
     }
 }
 
@@ -166,11 +311,13 @@ fn shuffle_vec(items: Vec<Value>) -> Vec<Value> {
     items_clone
 }
 
-fn parse_modules(value: Value) -> Result<Vec<Value>, Box<dyn Error>> {
-    let modules = value["rows"].as_array().unwrap().iter()
-        .flat_map(|row| row.as_object().unwrap()["modules"].as_array().unwrap())
-        .filter(|module| module["pagedList"]["items"].is_array())
-        .flat_map(|module| module.as_object().unwrap()["pagedList"]["items"].as_array().unwrap().clone())
+fn parse_modules(value: Value) -> Result<Vec<Value>, BackendError> {
+    let rows = value["rows"].as_array().ok_or_else(|| BackendError::InvalidResponse("page without rows".to_string()))?;
+    let modules = rows.iter()
+        .filter_map(|row| row["modules"].as_array())
+        .flatten()
+        .filter_map(|module| module["pagedList"]["items"].as_array())
+        .flat_map(|items| items.iter().cloned())
         .collect::<Vec<Value>>();
 
     Ok(modules)
@@ -1,11 +1,12 @@
 use bytes::Bytes;
 use reqwest::blocking::{Client, Response};
-use reqwest::header;
+use reqwest::{header, StatusCode};
 use serde::Deserialize;
 use serde_json::Value;
 
 use std::error::Error;
-use std::time::Duration;
+use std::io::Read;
+use std::time::{Duration, Instant};
 use std::thread;
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
@@ -13,6 +14,7 @@ use log::{debug, error, info, warn};
 
 use crate::config::{Config, Tidal};
 use crate::state::{Message, PlayerBus};
+use crate::backend::error::BackendError;
 
 #[derive(Debug)]
 #[derive(Clone)]
@@ -60,6 +62,8 @@ struct RefreshAuthorization {
     access_token: String,
 }
 
+const PAGE_LIMIT: usize = 100;
+
 fn client_id() -> String {
     String::from_utf8(BASE64_STANDARD.decode([
         BASE64_STANDARD.decode(b"WmxneVNuaGtiVzUw").unwrap(),
@@ -109,7 +113,7 @@ impl DeviceAuthorization {
                 config.tidal.token_type = session_response.token_type;
                 config.tidal.access_token = session_response.access_token;
                 config.tidal.refresh_token = session_response.refresh_token;
-                config.save();
+                config.save()?;
 
                 return Session::try_from_file(config)
             }
@@ -130,7 +134,6 @@ struct ResponseSession {
 impl Session {
     pub(super) fn setup(config: &mut Config, player_bus: PlayerBus) -> Session {
         player_bus.publish_message(Message::TidalBackendStarted);
-        Session::check_internet_connection();
 
         match Session::try_from_file(config) {
             Ok(session) => {
@@ -231,18 +234,27 @@ impl Session {
 
         config.tidal.token_type = refresh_auth_response.token_type;
         config.tidal.access_token = refresh_auth_response.access_token;
-        config.save();
+        config.save()?;
         info!("[Session] refreshed with success");
 
         Ok(())
     }
-    fn check_internet_connection() {
-        let res = reqwest::blocking::Client::default().get("https://api.tidal.com/").send();
-        if res.is_err() {
+    pub(super) fn is_reachable() -> bool {
+        Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .and_then(|client| client.get("https://api.tidal.com/").send())
+            .is_ok()
+    }
+    pub(super) fn wait_for_internet_connection(attempts: u32) -> bool {
+        for _ in 0..attempts {
+            if Self::is_reachable() {
+                return true;
+            }
             warn!("Wait for internet connection to tidal, next try... ");
             thread::sleep(Duration::from_secs(2));
-            Self::check_internet_connection()
         }
+        false
     }
     fn build_client(&self) -> Client {
         let mut headers = header::HeaderMap::new();
@@ -268,6 +280,28 @@ impl Session {
         let result: Value = serde_json::from_str(&body)?;
         Ok(result)
     }
+    pub(super) fn get_all_favorites(&self) -> Result<Vec<Value>, Box<dyn Error>> {
+        self.get_all_items(&format!("users/{}/favorites/tracks", self.user_id))
+    }
+    pub(super) fn get_all_playlist_tracks(&self, playlist_id: &str) -> Result<Vec<Value>, Box<dyn Error>> {
+        self.get_all_items(&format!("playlists/{}/tracks", playlist_id))
+    }
+    fn get_all_items(&self, path: &str) -> Result<Vec<Value>, Box<dyn Error>> {
+        let mut items: Vec<Value> = vec![];
+
+        loop {
+            let response = self.request(format!("{}/{}?countryCode={}&limit={}&offset={}", self.api_path, path, self.country_code, PAGE_LIMIT, items.len()))?;
+            let page: Value = serde_json::from_str(&response.text()?)?;
+            let page_items = page["items"].as_array().cloned().unwrap_or_default();
+            let total = page["totalNumberOfItems"].as_u64().unwrap_or_default() as usize;
+
+            let last_page = page_items.len() < PAGE_LIMIT;
+            items.extend(page_items);
+            if last_page || items.len() >= total {
+                return Ok(items);
+            }
+        }
+    }
     pub(super) fn get_favorites(&self) -> Result<Value, Box<dyn Error>> {
         let response = self.request(format!("{}/users/{}/favorites/tracks?countryCode={}&limit=100&offset=0", self.api_path, self.user_id, self.country_code))?;
         let body = response.text()?;
@@ -286,6 +320,12 @@ impl Session {
         let result: Value = serde_json::from_str(&body)?;
         Ok(result)
     }
+    pub(super) fn get_album_details(&self, album_id: &str) -> Result<Value, Box<dyn Error>> {
+        let response = self.request(format!("{}/albums/{}?countryCode={}&deviceType=BROWSER", self.api_path, album_id, self.country_code))?;
+        let body = response.text()?;
+        let result: Value = serde_json::from_str(&body)?;
+        Ok(result)
+    }
     pub(super) fn get_artist(&self, artist_id: &str) -> Result<Value, Box<dyn Error>> {
         let response = self.request(format!("{}/artists/{}/toptracks?countryCode={}&deviceType=BROWSER", self.api_path, artist_id, self.country_code))?;
         let body = response.text()?;
@@ -298,11 +338,27 @@ impl Session {
         let result: Value = serde_json::from_str(&body)?;
         Ok(result)
     }
-    pub(super) fn add_track_to_favorites(&self, track_id: &str) -> Result<(), Box<dyn Error>> {
+    pub(super) fn get_lyrics(&self, track_id: &str) -> Result<Option<Value>, Box<dyn Error>> {
+        let response = self.request(format!("{}/tracks/{}/lyrics?countryCode={}&deviceType=BROWSER", self.api_path, track_id, self.country_code))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = response.error_for_status()?.text()?;
+        let result: Value = serde_json::from_str(&body)?;
+        Ok(Some(result))
+    }
+    // Client errors are final, server and network errors may pass on a retry.
+    pub(super) fn add_track_to_favorites(&self, track_id: &str) -> Result<(), BackendError> {
         self.build_client().post(format!("{}/users/{}/favorites/tracks?countryCode={}&deviceType=BROWSER", self.api_path, self.user_id, self.country_code))
             .form(&[("trackId", track_id)])
-            .send()?;
-        Ok(())
+            .send()?
+            .error_for_status()
+            .map(|_| ())
+            .map_err(|err| match err.status() {
+                Some(StatusCode::NOT_FOUND) => BackendError::NotFound(format!("track {}", track_id)),
+                Some(status) if status.is_client_error() => BackendError::InvalidResponse(format!("favorites answered {}", status)),
+                _ => BackendError::from(err),
+            })
     }
     pub(super) fn get_track_url(&mut self, track_id: String) -> Result<String, Box<dyn Error>> {
         let download_url = format!("{}/tracks/{}/urlpostpaywall?sessionId={}&urlusagemode=STREAM&audioquality={}&assetpresentation=FULL", self.api_path, track_id, self.session_id, self.audio_quality);
@@ -333,6 +389,30 @@ impl Session {
 
         Ok(file_response.bytes()?)
     }
+    pub(super) fn get_track_bytes_with_rate_limit(&mut self, track_id: String, bytes_per_second: u64) -> Result<Bytes, Box<dyn Error>> {
+        let url = self.get_track_url(track_id.clone())?;
+
+        let mut file_response = Client::builder()
+            .timeout(Duration::from_secs(3600))
+            .build()?.get(url).send()?;
+
+        let started = Instant::now();
+        let mut output: Vec<u8> = vec![];
+        let mut buffer = [0u8; 16384];
+
+        loop {
+            let read = file_response.read(&mut buffer)?;
+            if read == 0 {
+                return Ok(Bytes::from(output));
+            }
+            output.extend_from_slice(&buffer[..read]);
+
+            let expected = Duration::from_secs_f64(output.len() as f64 / bytes_per_second as f64);
+            if let Some(ahead) = expected.checked_sub(started.elapsed()) {
+                thread::sleep(ahead);
+            }
+        }
+    }
     pub(super) fn get_cover_bytes(&self, cover_url: String) -> Result<Bytes, Box<dyn Error>> {
         let file_response = Client::builder()
             .timeout(Duration::from_secs(500))
@@ -0,0 +1,326 @@
+use std::{error::Error, time::Duration};
+
+use log::debug;
+
+use crate::playlist::{Track, TrackMetadata};
+
+use super::{ssdp::MediaServer, TRACK_ID_PREFIX};
+
+const SERVICE_TYPE: &str = "urn:schemas-upnp-org:service:ContentDirectory:1";
+const PAGE_SIZE: u32 = 200;
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub(super) struct DidlItem {
+    pub id: String,
+    pub parent_id: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub album_art: String,
+    pub resource: Option<String>,
+    pub duration: Duration,
+    pub track_number: u32,
+    pub date: String,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub(super) struct DidlContainer {
+    pub id: String,
+}
+
+#[derive(Debug)]
+#[derive(Default)]
+pub(super) struct DidlPage {
+    pub items: Vec<DidlItem>,
+    pub containers: Vec<DidlContainer>,
+    pub number_returned: u32,
+    pub total_matches: u32,
+}
+
+impl DidlItem {
+    pub fn to_track(&self) -> Track {
+        Track {
+            id: format!("{}{}", TRACK_ID_PREFIX, self.id),
+            title: self.title.clone(),
+            artist_name: self.artist.clone(),
+            album_name: self.album.clone(),
+            album_image: self.album_art.clone(),
+            duration: self.duration,
+            metadata: TrackMetadata {
+                track_number: self.track_number,
+                release_date: self.date.clone(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct ContentDirectory {
+    server: MediaServer,
+}
+
+impl ContentDirectory {
+    pub fn new(server: MediaServer) -> Self {
+        Self { server }
+    }
+
+    pub fn browse_children(&self, object_id: &str) -> Result<DidlPage, Box<dyn Error>> {
+        let mut result = DidlPage::default();
+
+        loop {
+            let page = self.browse(object_id, "BrowseDirectChildren", result.number_returned)?;
+            result.number_returned += page.number_returned;
+            result.total_matches = page.total_matches;
+            result.items.extend(page.items);
+            result.containers.extend(page.containers);
+
+            if page.number_returned == 0 || result.number_returned >= result.total_matches {
+                return Ok(result);
+            }
+        }
+    }
+
+    pub fn browse_metadata(&self, object_id: &str) -> Result<Option<DidlItem>, Box<dyn Error>> {
+        let page = self.browse(object_id, "BrowseMetadata", 0)?;
+        Ok(page.items.into_iter().next())
+    }
+
+    pub fn search(&self, container_id: &str, criteria: &str, limit: u32) -> Result<DidlPage, Box<dyn Error>> {
+        let mut result = DidlPage::default();
+
+        loop {
+            let arguments = format!(
+                "<ContainerID>{}</ContainerID><SearchCriteria>{}</SearchCriteria><Filter>*</Filter><StartingIndex>{}</StartingIndex><RequestedCount>{}</RequestedCount><SortCriteria></SortCriteria>",
+                escape_xml(container_id), escape_xml(criteria), result.number_returned, PAGE_SIZE
+            );
+            let page = self.call("Search", &arguments)?;
+            result.number_returned += page.number_returned;
+            result.total_matches = page.total_matches;
+            result.items.extend(page.items);
+
+            if page.number_returned == 0 || result.number_returned >= result.total_matches || result.number_returned >= limit {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn browse(&self, object_id: &str, browse_flag: &str, starting_index: u32) -> Result<DidlPage, Box<dyn Error>> {
+        let arguments = format!(
+            "<ObjectID>{}</ObjectID><BrowseFlag>{}</BrowseFlag><Filter>*</Filter><StartingIndex>{}</StartingIndex><RequestedCount>{}</RequestedCount><SortCriteria></SortCriteria>",
+            escape_xml(object_id), browse_flag, starting_index, PAGE_SIZE
+        );
+        self.call("Browse", &arguments)
+    }
+
+    fn call(&self, action: &str, arguments: &str) -> Result<DidlPage, Box<dyn Error>> {
+        let envelope = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body><u:{action} xmlns:u=\"{SERVICE_TYPE}\">{arguments}</u:{action}></s:Body></s:Envelope>"
+        );
+
+        let response = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?
+            .post(&self.server.control_url)
+            .header("Content-Type", "text/xml; charset=\"utf-8\"")
+            .header("SOAPACTION", format!("\"{SERVICE_TYPE}#{action}\""))
+            .body(envelope)
+            .send()?;
+
+        if !response.status().is_success() {
+            return Err(format!("ContentDirectory {} failed (status: {})", action, response.status()).into());
+        }
+
+        let body = response.text()?;
+        debug!("[UPnP] {} response: {}", action, body);
+
+        parse_response(&body)
+    }
+}
+
+fn parse_response(body: &str) -> Result<DidlPage, Box<dyn Error>> {
+    let document = roxmltree::Document::parse(body)?;
+    let element_text = |name: &str| document.descendants()
+        .find(|node| node.has_tag_name(name))
+        .and_then(|node| node.text())
+        .unwrap_or_default()
+        .to_string();
+
+    let mut page = parse_didl(&element_text("Result"))?;
+    page.number_returned = element_text("NumberReturned").parse().unwrap_or_default();
+    page.total_matches = element_text("TotalMatches").parse().unwrap_or_default();
+
+    Ok(page)
+}
+
+fn parse_didl(didl: &str) -> Result<DidlPage, Box<dyn Error>> {
+    let mut page = DidlPage::default();
+    if didl.is_empty() {
+        return Ok(page);
+    }
+
+    let document = roxmltree::Document::parse(didl)?;
+
+    for container in document.descendants().filter(|node| node.has_tag_name("container")) {
+        page.containers.push(DidlContainer {
+            id: container.attribute("id").unwrap_or_default().to_string(),
+        });
+    }
+
+    for item in document.descendants().filter(|node| node.has_tag_name("item")) {
+        let child_text = |name: &str| item.children()
+            .find(|node| node.has_tag_name(name))
+            .and_then(|node| node.text())
+            .unwrap_or_default()
+            .to_string();
+
+        let resource = item.children()
+            .filter(|node| node.has_tag_name("res"))
+            .find(|node| node.attribute("protocolInfo").is_some_and(is_flac));
+
+        let artist = match child_text("artist") {
+            artist if artist.is_empty() => child_text("creator"),
+            artist => artist,
+        };
+
+        page.items.push(DidlItem {
+            id: item.attribute("id").unwrap_or_default().to_string(),
+            parent_id: item.attribute("parentID").unwrap_or_default().to_string(),
+            title: child_text("title"),
+            artist,
+            album: child_text("album"),
+            album_art: child_text("albumArtURI"),
+            resource: resource.and_then(|node| node.text()).map(|url| url.trim().to_string()),
+            duration: resource.and_then(|node| node.attribute("duration")).map(parse_duration).unwrap_or_default(),
+            track_number: child_text("originalTrackNumber").parse().unwrap_or_default(),
+            date: child_text("date"),
+        });
+    }
+
+    Ok(page)
+}
+
+fn is_flac(protocol_info: &str) -> bool {
+    protocol_info.contains("audio/flac") || protocol_info.contains("audio/x-flac")
+}
+
+fn parse_duration(duration: &str) -> Duration {
+    let seconds = duration.split(':')
+        .map(|part| part.parse::<f64>().unwrap_or_default())
+        .fold(0.0, |total, part| total * 60.0 + part);
+    Duration::from_secs_f64(seconds)
+}
+
+fn escape_xml(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{escape_xml, parse_response, ContentDirectory};
+    use crate::backend::upnp::{ssdp::MediaServer, stub::serve_once};
+
+    const DIDL: &str = r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">
+<container id="64$1" parentID="64" restricted="1"><dc:title>Albums</dc:title></container>
+<item id="64$0$1" parentID="64$0" restricted="1">
+<dc:title>Song</dc:title>
+<dc:creator>Creator</dc:creator>
+<upnp:album>Album</upnp:album>
+<upnp:albumArtURI>http://nas/art.jpg</upnp:albumArtURI>
+<upnp:originalTrackNumber>3</upnp:originalTrackNumber>
+<dc:date>2021-05-01</dc:date>
+<res protocolInfo="http-get:*:audio/mpeg:*" duration="0:03:25.500">http://nas/song.mp3</res>
+<res protocolInfo="http-get:*:audio/flac:*" duration="0:03:25.500"> http://nas/song.flac </res>
+</item>
+<item id="64$0$2" parentID="64$0" restricted="1">
+<dc:title>Lossy</dc:title>
+<upnp:artist>Artist</upnp:artist>
+<res protocolInfo="http-get:*:audio/mpeg:*" duration="0:01:00">http://nas/lossy.mp3</res>
+</item>
+</DIDL-Lite>"#;
+
+    fn soap_response(action: &str, number_returned: u32, total_matches: u32) -> String {
+        format!(
+            "<?xml version=\"1.0\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body><u:{action}Response xmlns:u=\"urn:schemas-upnp-org:service:ContentDirectory:1\"><Result>{}</Result><NumberReturned>{number_returned}</NumberReturned><TotalMatches>{total_matches}</TotalMatches><UpdateID>1</UpdateID></u:{action}Response></s:Body></s:Envelope>",
+            escape_xml(DIDL)
+        )
+    }
+
+    fn content_directory(url: &str) -> ContentDirectory {
+        ContentDirectory::new(MediaServer { friendly_name: "NAS".to_string(), control_url: format!("{}/ctl/ContentDir", url) })
+    }
+
+    #[test]
+    fn browse_response() {
+        let page = parse_response(&soap_response("Browse", 3, 3)).unwrap();
+
+        assert_eq!(page.number_returned, 3);
+        assert_eq!(page.total_matches, 3);
+        assert_eq!(page.containers.len(), 1);
+        assert_eq!(page.containers[0].id, "64$1");
+        assert_eq!(page.items.len(), 2);
+
+        let item = &page.items[0];
+        assert_eq!(item.id, "64$0$1");
+        assert_eq!(item.parent_id, "64$0");
+        assert_eq!(item.title, "Song");
+        assert_eq!(item.artist, "Creator");
+        assert_eq!(item.album, "Album");
+        assert_eq!(item.album_art, "http://nas/art.jpg");
+        assert_eq!(item.resource.as_deref(), Some("http://nas/song.flac"));
+        assert_eq!(item.duration, Duration::from_millis(205_500));
+        assert_eq!(item.track_number, 3);
+        assert_eq!(item.date, "2021-05-01");
+
+        let track = item.to_track();
+        assert_eq!(track.id, "upnp:64$0$1");
+        assert_eq!(track.metadata.track_number, 3);
+    }
+
+    #[test]
+    fn item_without_flac_resource() {
+        let page = parse_response(&soap_response("Search", 3, 3)).unwrap();
+
+        let item = &page.items[1];
+        assert_eq!(item.artist, "Artist");
+        assert_eq!(item.resource, None);
+    }
+
+    #[test]
+    fn empty_result() {
+        let body = "<?xml version=\"1.0\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body><u:SearchResponse xmlns:u=\"urn:schemas-upnp-org:service:ContentDirectory:1\"><Result></Result><NumberReturned>0</NumberReturned><TotalMatches>0</TotalMatches></u:SearchResponse></s:Body></s:Envelope>";
+        let page = parse_response(body).unwrap();
+
+        assert!(page.items.is_empty());
+        assert!(page.containers.is_empty());
+    }
+
+    #[test]
+    fn search_request() {
+        let (url, request) = serve_once(&soap_response("Search", 3, 3));
+        let page = content_directory(&url).search("0", "upnp:class derivedfrom \"object.item.audioItem\"", 1000).unwrap();
+        let request = request.join().unwrap();
+
+        assert!(request.starts_with("POST /ctl/ContentDir HTTP/1.1"));
+        assert!(request.to_lowercase().contains("soapaction: \"urn:schemas-upnp-org:service:contentdirectory:1#search\""));
+        assert!(request.contains("<SearchCriteria>upnp:class derivedfrom &quot;object.item.audioItem&quot;</SearchCriteria>"));
+        assert_eq!(page.items.len(), 2);
+    }
+
+    #[test]
+    fn browse_metadata_request() {
+        let (url, request) = serve_once(&soap_response("Browse", 1, 1));
+        let item = content_directory(&url).browse_metadata("64$0$1").unwrap();
+
+        assert!(request.join().unwrap().contains("<BrowseFlag>BrowseMetadata</BrowseFlag>"));
+        assert_eq!(item.map(|item| item.title), Some("Song".to_string()));
+    }
+}
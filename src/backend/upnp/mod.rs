@@ -0,0 +1,185 @@
+use std::time::Duration;
+use bytes::Bytes;
+use log::{info, warn};
+use rand::thread_rng;
+use rand::seq::SliceRandom;
+
+use crate::{config::Config, state::{Message, PlayerBus}, playlist::Track};
+use self::content_directory::{ContentDirectory, DidlItem};
+use super::{error::BackendError, Backend};
+
+mod content_directory;
+mod ssdp;
+#[cfg(test)]
+mod stub;
+
+pub const TRACK_ID_PREFIX: &str = "upnp:";
+
+const ROOT_CONTAINER: &str = "0";
+const AUDIO_ITEMS_CRITERIA: &str = "upnp:class derivedfrom \"object.item.audioItem\"";
+const DISCOVERY_LIMIT: u32 = 1000;
+// Tracks queued at start, picked at random from the discovered items, radio and albums fill the rest.
+const DISCOVERY_TRACKS: usize = 20;
+const BROWSE_DEPTH: u8 = 6;
+
+#[derive(Clone)]
+pub struct UpnpBackend {
+    content_directory: Option<ContentDirectory>,
+}
+
+impl Backend for UpnpBackend {
+    fn init(config: &mut Config, player_bus: PlayerBus) -> Self {
+        player_bus.publish_message(Message::UpnpBackendStarted);
+
+        let timeout = Duration::from_secs(config.upnp.discovery_timeout as u64);
+        match ssdp::find_media_server(&config.upnp.location, &config.upnp.friendly_name, timeout) {
+            Some(server) => {
+                info!("[UPnP] using media server '{}' ({})", server.friendly_name, server.control_url);
+                player_bus.publish_message(Message::UpnpBackendInitialized);
+                Self { content_directory: Some(ContentDirectory::new(server)) }
+            },
+            None => {
+                warn!("[UPnP] media server not found");
+                player_bus.publish_message(Message::UpnpBackendUnavailable);
+                Self { content_directory: None }
+            },
+        }
+    }
+    fn discovery(&self, discovery_fn: impl Fn(Track)) -> Result<(), BackendError> {
+        self.content_directory()?;
+        let mut items = match self.search_audio_items() {
+            Ok(items) => items,
+            Err(err) => {
+                info!("[UPnP] search not supported ({:?}), browse from root", err);
+                self.browse_recursive(ROOT_CONTAINER, BROWSE_DEPTH)
+            },
+        };
+        items.shuffle(&mut thread_rng());
+
+        for item in items.into_iter().take(DISCOVERY_TRACKS) {
+            discovery_fn(item.to_track());
+        }
+        Ok(())
+    }
+    fn get_track(&mut self, track_id: String) -> Result<Bytes, BackendError> {
+        let item = self.content_directory()?
+            .browse_metadata(Self::object_id(&track_id))?
+            .ok_or_else(|| BackendError::NotFound(format!("UPnP item {}", track_id)))?;
+        let resource = item.resource.ok_or_else(|| BackendError::NotFound(format!("FLAC resource of UPnP item {}", track_id)))?;
+
+        let file_response = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(300))
+            .build()?
+            .get(resource)
+            .send()?
+            .error_for_status()?;
+
+        Ok(file_response.bytes()?)
+    }
+    fn get_cover(&self, cover_url: String) -> Result<Bytes, BackendError> {
+        let file_response = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(60))
+            .build()?
+            .get(&cover_url)
+            .send()?
+            .error_for_status()?;
+
+        Ok(file_response.bytes()?)
+    }
+    fn discovery_radio(&self, track_id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+        info!("[UPnP] Discover tracks around: {}", track_id);
+        let item = self.item(track_id)?;
+
+        let mut items = self.browse_recursive(&item.parent_id, 1);
+        items.shuffle(&mut thread_rng());
+        discovery_fn(Self::to_tracks(items));
+        Ok(())
+    }
+    fn discovery_track(&self, track_id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+        discovery_fn(vec![self.item(track_id)?.to_track()]);
+        Ok(())
+    }
+    fn discovery_album(&self, album_id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+        self.content_directory()?;
+        let tracks = Self::to_tracks(self.browse_recursive(Self::object_id(album_id), 1));
+
+        info!("[UPnP] Discover tracks {:?} from container: {}", tracks, album_id);
+        discovery_fn(tracks);
+        Ok(())
+    }
+    fn discovery_artist(&self, artist_id: &str, discovery_fn: impl Fn(Vec<Track>)) -> Result<(), BackendError> {
+        self.content_directory()?;
+        let mut items = self.browse_recursive(Self::object_id(artist_id), 2);
+        items.shuffle(&mut thread_rng());
+        let tracks = Self::to_tracks(items);
+
+        info!("[UPnP] Discover tracks {:?} from container: {}", tracks, artist_id);
+        discovery_fn(tracks);
+        Ok(())
+    }
+    fn add_track_to_favorites(&self, track_id: &str) -> Result<(), BackendError> {
+        info!("[UPnP] favorites are not supported by media servers, ignore: {}", track_id);
+        Ok(())
+    }
+}
+
+impl UpnpBackend {
+    pub fn owns(track_id: &str) -> bool {
+        track_id.starts_with(TRACK_ID_PREFIX)
+    }
+
+    fn object_id(track_id: &str) -> &str {
+        track_id.strip_prefix(TRACK_ID_PREFIX).unwrap_or(track_id)
+    }
+
+    fn content_directory(&self) -> Result<&ContentDirectory, BackendError> {
+        self.content_directory.as_ref().ok_or_else(|| BackendError::Unavailable("UPnP media server not found".to_string()))
+    }
+
+    fn item(&self, track_id: &str) -> Result<DidlItem, BackendError> {
+        self.content_directory()?
+            .browse_metadata(Self::object_id(track_id))?
+            .ok_or_else(|| BackendError::NotFound(format!("UPnP item {}", track_id)))
+    }
+
+    fn search_audio_items(&self) -> Result<Vec<DidlItem>, BackendError> {
+        let page = self.content_directory()?.search(ROOT_CONTAINER, AUDIO_ITEMS_CRITERIA, DISCOVERY_LIMIT)?;
+        Ok(Self::playable(page.items))
+    }
+
+    fn browse_recursive(&self, object_id: &str, depth: u8) -> Vec<DidlItem> {
+        let content_directory = match self.content_directory() {
+            Ok(content_directory) => content_directory,
+            Err(_) => return vec![],
+        };
+
+        let mut items: Vec<DidlItem> = vec![];
+        let mut containers = vec![(object_id.to_string(), depth)];
+
+        while let Some((container_id, depth)) = containers.pop() {
+            if items.len() >= DISCOVERY_LIMIT as usize {
+                break;
+            }
+
+            match content_directory.browse_children(&container_id) {
+                Ok(page) => {
+                    items.extend(Self::playable(page.items));
+                    if depth > 1 {
+                        containers.extend(page.containers.into_iter().map(|container| (container.id, depth - 1)));
+                    }
+                },
+                Err(err) => warn!("[UPnP] browse '{}' error: {:?}", container_id, err),
+            }
+        }
+
+        items
+    }
+
+    fn playable(items: Vec<DidlItem>) -> Vec<DidlItem> {
+        items.into_iter().filter(|item| item.resource.is_some()).collect()
+    }
+
+    fn to_tracks(items: Vec<DidlItem>) -> Vec<Track> {
+        items.iter().map(DidlItem::to_track).collect()
+    }
+}
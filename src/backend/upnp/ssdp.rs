@@ -0,0 +1,164 @@
+use std::{error::Error, net::UdpSocket, time::{Duration, Instant}};
+
+use log::{debug, info, warn};
+use reqwest::Url;
+
+const SSDP_ADDRESS: &str = "239.255.255.250:1900";
+const MEDIA_SERVER_TYPE: &str = "urn:schemas-upnp-org:device:MediaServer:1";
+const CONTENT_DIRECTORY_TYPE: &str = "urn:schemas-upnp-org:service:ContentDirectory";
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub(super) struct MediaServer {
+    pub friendly_name: String,
+    pub control_url: String,
+}
+
+pub(super) fn discover_locations(timeout: Duration) -> Result<Vec<String>, Box<dyn Error>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(Duration::from_millis(250)))?;
+
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: {}\r\nST: {}\r\n\r\n",
+        SSDP_ADDRESS, timeout.as_secs().max(1), MEDIA_SERVER_TYPE
+    );
+    socket.send_to(request.as_bytes(), SSDP_ADDRESS)?;
+
+    let mut locations: Vec<String> = vec![];
+    let mut buffer = [0u8; 2048];
+    let started = Instant::now();
+
+    while started.elapsed() < timeout {
+        let size = match socket.recv_from(&mut buffer) {
+            Ok((size, _)) => size,
+            Err(_) => continue,
+        };
+
+        let response = String::from_utf8_lossy(&buffer[..size]);
+        debug!("[UPnP] SSDP response: {}", response);
+
+        if let Some(location) = parse_header(&response, "location") {
+            if !locations.contains(&location) {
+                info!("[UPnP] media server found: {}", location);
+                locations.push(location);
+            }
+        }
+    }
+
+    Ok(locations)
+}
+
+pub(super) fn describe(location: &str) -> Result<MediaServer, Box<dyn Error>> {
+    let body = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?
+        .get(location)
+        .send()?
+        .text()?;
+    let document = roxmltree::Document::parse(&body)?;
+
+    let friendly_name = document.descendants()
+        .find(|node| node.has_tag_name("friendlyName"))
+        .and_then(|node| node.text())
+        .unwrap_or_default()
+        .to_string();
+
+    let base_url = document.descendants()
+        .find(|node| node.has_tag_name("URLBase"))
+        .and_then(|node| node.text())
+        .unwrap_or(location);
+
+    let control_path = document.descendants()
+        .filter(|node| node.has_tag_name("service"))
+        .find(|service| service.children()
+            .any(|child| child.has_tag_name("serviceType") && child.text().is_some_and(|text| text.starts_with(CONTENT_DIRECTORY_TYPE))))
+        .and_then(|service| service.children().find(|child| child.has_tag_name("controlURL")))
+        .and_then(|node| node.text())
+        .ok_or("ContentDirectory service not found in device description")?;
+
+    let control_url = Url::parse(base_url.trim())?.join(control_path.trim())?.to_string();
+
+    Ok(MediaServer { friendly_name, control_url })
+}
+
+pub(super) fn find_media_server(location: &str, friendly_name: &str, timeout: Duration) -> Option<MediaServer> {
+    let locations = if location.is_empty() {
+        discover_locations(timeout).unwrap_or_else(|err| {
+            warn!("[UPnP] SSDP discovery failed: {:?}", err);
+            vec![]
+        })
+    } else {
+        vec![location.to_string()]
+    };
+
+    locations.iter()
+        .filter_map(|location| match describe(location) {
+            Ok(server) => Some(server),
+            Err(err) => {
+                warn!("[UPnP] device description '{}' error: {:?}", location, err);
+                None
+            },
+        })
+        .find(|server| friendly_name.is_empty() || server.friendly_name == friendly_name)
+}
+
+fn parse_header(response: &str, name: &str) -> Option<String> {
+    response.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{describe, find_media_server, parse_header};
+    use crate::backend::upnp::stub::serve_once;
+
+    const SSDP_RESPONSE: &str = "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=1800\r\nEXT:\r\nLocation: http://192.168.1.10:8200/rootDesc.xml\r\nSERVER: Linux DLNADOC/1.50 UPnP/1.0 MiniDLNA/1.3.0\r\nST: urn:schemas-upnp-org:device:MediaServer:1\r\nUSN: uuid:4d696e69-444c-164e-9d41-b827ebd3a1e1::urn:schemas-upnp-org:device:MediaServer:1\r\n\r\n";
+
+    const DEVICE_DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:MediaServer:1</deviceType>
+    <friendlyName>NAS</friendlyName>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:ConnectionManager:1</serviceType>
+        <controlURL>/ctl/ConnectionMgr</controlURL>
+      </service>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:ContentDirectory:1</serviceType>
+        <controlURL>/ctl/ContentDir</controlURL>
+      </service>
+    </serviceList>
+  </device>
+</root>"#;
+
+    #[test]
+    fn ssdp_response_location() {
+        assert_eq!(parse_header(SSDP_RESPONSE, "location"), Some("http://192.168.1.10:8200/rootDesc.xml".to_string()));
+        assert_eq!(parse_header(SSDP_RESPONSE, "st"), Some("urn:schemas-upnp-org:device:MediaServer:1".to_string()));
+        assert_eq!(parse_header(SSDP_RESPONSE, "al"), None);
+    }
+
+    #[test]
+    fn describe_content_directory() {
+        let (url, request) = serve_once(DEVICE_DESCRIPTION);
+        let server = describe(&format!("{}/rootDesc.xml", url)).unwrap();
+
+        assert!(request.join().unwrap().starts_with("GET /rootDesc.xml HTTP/1.1"));
+        assert_eq!(server.friendly_name, "NAS");
+        assert_eq!(server.control_url, format!("{}/ctl/ContentDir", url));
+    }
+
+    #[test]
+    fn find_media_server_by_friendly_name() {
+        let (url, _) = serve_once(DEVICE_DESCRIPTION);
+        assert!(find_media_server(&url, "NAS", Duration::ZERO).is_some());
+
+        let (url, _) = serve_once(DEVICE_DESCRIPTION);
+        assert!(find_media_server(&url, "Other", Duration::ZERO).is_none());
+    }
+}
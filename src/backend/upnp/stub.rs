@@ -0,0 +1,44 @@
+use std::{io::{Read, Write}, net::TcpListener, thread::{self, JoinHandle}};
+
+// Local stand-in for a media server, answers one HTTP request with the body and returns the request it received.
+pub(super) fn serve_once(body: &str) -> (String, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let body = body.to_string();
+
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let request = read_request(&mut stream);
+
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+        stream.write_all(response.as_bytes()).unwrap();
+        request
+    });
+
+    (url, handle)
+}
+
+fn read_request(stream: &mut impl Read) -> String {
+    let mut request: Vec<u8> = vec![];
+    let mut buffer = [0u8; 4096];
+
+    loop {
+        let size = stream.read(&mut buffer).unwrap();
+        request.extend_from_slice(&buffer[..size]);
+
+        let text = String::from_utf8_lossy(&request).to_string();
+        if let Some((headers, content)) = text.split_once("\r\n\r\n") {
+            let content_length = headers.lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("content-length"))
+                .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                .unwrap_or_default();
+            if content.len() >= content_length {
+                return text;
+            }
+        }
+        if size == 0 {
+            return text;
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{error::Error, path::PathBuf};
 
 use ini::{Ini, Properties};
 
@@ -8,6 +8,8 @@ trait ParseIni {
     fn get_bool(&self, name: &str) -> bool; 
     fn get_bool_with_default(&self, name: &str, default: bool) -> bool;
     fn get_u16_with_default(&self, name: &str, default: u16) -> u16;
+    fn get_u64_with_default(&self, name: &str, default: u64) -> u64;
+    fn get_list(&self, name: &str) -> Vec<String>;
 }
 
 impl ParseIni for Option<&Properties> {
@@ -32,6 +34,18 @@ impl ParseIni for Option<&Properties> {
             .map(|val| val.parse::<u16>().unwrap_or(default))
             .unwrap_or(default)
     }
+    fn get_u64_with_default(&self, name: &str, default: u64) -> u64 {
+        self.map(|properties| properties.get(name)).flatten()
+            .map(|val| val.parse::<u64>().unwrap_or(default))
+            .unwrap_or(default)
+    }
+    fn get_list(&self, name: &str) -> Vec<String> {
+        self.get_string(name)
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    }
 }
 
 fn bool_to_string(value: bool) -> String {
@@ -70,6 +84,7 @@ impl Tidal {
 #[derive(Clone)]
 pub struct Player {
     pub without_cold_start: bool,
+    pub offline: bool,
 }
 
 impl Player {
@@ -77,11 +92,13 @@ impl Player {
         let properties = conf.section(Some("Player"));
         Self {
             without_cold_start: properties.get_bool_with_default("without_cold_start", false),
+            offline: properties.get_bool_with_default("offline", false),
         }
     }
     fn prepare_to_save(&self, ini: &mut Ini) {
         ini.with_section(Some("Player"))
-            .set("without_cold_start", bool_to_string(self.without_cold_start));
+            .set("without_cold_start", bool_to_string(self.without_cold_start))
+            .set("offline", bool_to_string(self.offline));
     }
 }
 
@@ -93,6 +110,8 @@ pub struct Gui {
     pub window_y: u16,
     pub display_cover_background: bool,
     pub display_cover_foreground: bool,
+    pub cover_cache_path: String,
+    pub cover_cache_max_mb: u64,
 }
 
 impl Gui {
@@ -104,6 +123,8 @@ impl Gui {
             window_y: properties.get_u16_with_default("window_y", 600),
             display_cover_background: properties.get_bool_with_default("display_cover_background", true),
             display_cover_foreground: properties.get_bool_with_default("display_cover_foreground", true),
+            cover_cache_path: properties.get_string("cover_cache_path"),
+            cover_cache_max_mb: properties.get_u64_with_default("cover_cache_max_mb", 64),
         }
     }
     fn prepare_to_save(&self, ini: &mut Ini) {
@@ -112,7 +133,28 @@ impl Gui {
             .set("window_x", self.window_x.to_string())
             .set("window_y", self.window_y.to_string())
             .set("display_cover_background", bool_to_string(self.display_cover_background))
-            .set("display_cover_foreground", bool_to_string(self.display_cover_foreground));
+            .set("display_cover_foreground", bool_to_string(self.display_cover_foreground))
+            .set("cover_cache_path", self.cover_cache_path.clone())
+            .set("cover_cache_max_mb", self.cover_cache_max_mb.to_string());
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct ExporterLayout {
+    pub file_name_template: String,
+}
+
+impl ExporterLayout {
+    fn init(conf: &Ini) -> Self {
+        let properties = conf.section(Some("Exporter"));
+        Self {
+            file_name_template: properties.get_string("file_name_template"),
+        }
+    }
+    fn prepare_to_save(&self, ini: &mut Ini) {
+        ini.with_section(Some("Exporter"))
+            .set("file_name_template", self.file_name_template.clone());
     }
 }
 
@@ -132,6 +174,12 @@ pub struct ExporterFTP {
 pub struct ExporterFile {
     pub enabled: bool,
     pub path: String,
+    pub max_size_mb: u64,
+    pub eviction: String,
+    pub scrub_interval_hours: u64,
+    pub sidecar_cover: bool,
+    pub sidecar_lyrics: bool,
+    pub sidecar_playlist: bool,
 }
 
 impl crate::config::ExporterFile {
@@ -140,12 +188,24 @@ impl crate::config::ExporterFile {
         Self {
             enabled: properties.get_bool("enabled"),
             path: properties.get_string("path"),
+            max_size_mb: properties.get_u64_with_default("max_size_mb", 0),
+            eviction: properties.get_string_with_default("eviction", "lru"),
+            scrub_interval_hours: properties.get_u64_with_default("scrub_interval_hours", 0),
+            sidecar_cover: properties.get_bool("sidecar_cover"),
+            sidecar_lyrics: properties.get_bool("sidecar_lyrics"),
+            sidecar_playlist: properties.get_bool("sidecar_playlist"),
         }
     }
     fn prepare_to_save(&self, ini: &mut Ini) {
         ini.with_section(Some("ExporterFile"))
             .set("enabled", bool_to_string(self.enabled))
-            .set("path", self.path.clone());
+            .set("path", self.path.clone())
+            .set("max_size_mb", self.max_size_mb.to_string())
+            .set("eviction", self.eviction.clone())
+            .set("scrub_interval_hours", self.scrub_interval_hours.to_string())
+            .set("sidecar_cover", bool_to_string(self.sidecar_cover))
+            .set("sidecar_lyrics", bool_to_string(self.sidecar_lyrics))
+            .set("sidecar_playlist", bool_to_string(self.sidecar_playlist));
     }
 }
 
@@ -172,6 +232,278 @@ impl ExporterFTP {
     }
 }
 
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct ExporterWebDAV {
+    pub enabled: bool,
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    pub cache_read: bool,
+}
+
+impl ExporterWebDAV {
+    fn init(conf: &Ini) -> Self {
+        let properties = conf.section(Some("ExporterWebDAV"));
+        Self {
+            enabled: properties.get_bool("enabled"),
+            url: properties.get_string("url"),
+            username: properties.get_string("username"),
+            password: properties.get_string("password"),
+            cache_read: properties.get_bool("cache_read"),
+        }
+    }
+    fn prepare_to_save(&self, ini: &mut Ini) {
+        ini.with_section(Some("ExporterWebDAV"))
+            .set("enabled", bool_to_string(self.enabled))
+            .set("url", self.url.clone())
+            .set("username", self.username.clone())
+            .set("password", self.password.clone())
+            .set("cache_read", bool_to_string(self.cache_read));
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct ExporterSFTP {
+    pub enabled: bool,
+    pub server: String,
+    pub path: String,
+    pub username: String,
+    pub password: String,
+    pub private_key: String,
+    pub cache_read: bool,
+}
+
+impl ExporterSFTP {
+    fn init(conf: &Ini) -> Self {
+        let properties = conf.section(Some("ExporterSFTP"));
+        Self {
+            enabled: properties.get_bool("enabled"),
+            server: properties.get_string("server"),
+            path: properties.get_string("path"),
+            username: properties.get_string("username"),
+            password: properties.get_string("password"),
+            private_key: properties.get_string("private_key"),
+            cache_read: properties.get_bool("cache_read"),
+        }
+    }
+    fn prepare_to_save(&self, ini: &mut Ini) {
+        ini.with_section(Some("ExporterSFTP"))
+            .set("enabled", bool_to_string(self.enabled))
+            .set("server", self.server.clone())
+            .set("path", self.path.clone())
+            .set("username", self.username.clone())
+            .set("password", self.password.clone())
+            .set("private_key", self.private_key.clone())
+            .set("cache_read", bool_to_string(self.cache_read));
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct ExporterSMB {
+    pub enabled: bool,
+    pub server: String,
+    pub share: String,
+    pub password: String,
+    pub username: String,
+    pub workgroup: String,
+    pub cache_read: bool,
+}
+
+impl ExporterSMB {
+    fn init(conf: &Ini) -> Self {
+        let properties = conf.section(Some("Exporter_smb"));
+        Self {
+            enabled: properties.get_bool("enabled"),
+            server: properties.get_string("server"),
+            share: properties.get_string("share"),
+            password: properties.get_string("password"),
+            username: properties.get_string("username"),
+            workgroup: properties.get_string_with_default("workgroup", "WORKGROUP"),
+            cache_read: properties.get_bool("cache"),
+        }
+    }
+    fn prepare_to_save(&self, ini: &mut Ini) {
+        ini.with_section(Some("Exporter_smb"))
+            .set("enabled", bool_to_string(self.enabled))
+            .set("server", self.server.clone())
+            .set("share", self.share.clone())
+            .set("password", self.password.clone())
+            .set("username", self.username.clone())
+            .set("workgroup", self.workgroup.clone())
+            .set("cache", bool_to_string(self.cache_read));
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct ExporterS3 {
+    pub enabled: bool,
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
+    pub path_style: bool,
+    pub cache_read: bool,
+}
+
+impl ExporterS3 {
+    fn init(conf: &Ini) -> Self {
+        let properties = conf.section(Some("ExporterS3"));
+        Self {
+            enabled: properties.get_bool("enabled"),
+            endpoint: properties.get_string("endpoint"),
+            region: properties.get_string_with_default("region", "us-east-1"),
+            bucket: properties.get_string("bucket"),
+            prefix: properties.get_string("prefix"),
+            access_key: properties.get_string("access_key"),
+            secret_key: properties.get_string("secret_key"),
+            path_style: properties.get_bool_with_default("path_style", true),
+            cache_read: properties.get_bool("cache_read"),
+        }
+    }
+    fn prepare_to_save(&self, ini: &mut Ini) {
+        ini.with_section(Some("ExporterS3"))
+            .set("enabled", bool_to_string(self.enabled))
+            .set("endpoint", self.endpoint.clone())
+            .set("region", self.region.clone())
+            .set("bucket", self.bucket.clone())
+            .set("prefix", self.prefix.clone())
+            .set("access_key", self.access_key.clone())
+            .set("secret_key", self.secret_key.clone())
+            .set("path_style", bool_to_string(self.path_style))
+            .set("cache_read", bool_to_string(self.cache_read));
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Transcoder {
+    pub enabled: bool,
+    pub format: String,
+    pub bitrate_kbps: u64,
+    pub ffmpeg: String,
+    pub exporters: Vec<String>,
+}
+
+impl Transcoder {
+    fn init(conf: &Ini) -> Self {
+        let properties = conf.section(Some("Transcoder"));
+        Self {
+            enabled: properties.get_bool("enabled"),
+            format: properties.get_string_with_default("format", "opus"),
+            bitrate_kbps: properties.get_u64_with_default("bitrate_kbps", 160),
+            ffmpeg: properties.get_string_with_default("ffmpeg", "ffmpeg"),
+            exporters: properties.get_list("exporters"),
+        }
+    }
+    fn prepare_to_save(&self, ini: &mut Ini) {
+        ini.with_section(Some("Transcoder"))
+            .set("enabled", bool_to_string(self.enabled))
+            .set("format", self.format.clone())
+            .set("bitrate_kbps", self.bitrate_kbps.to_string())
+            .set("ffmpeg", self.ffmpeg.clone())
+            .set("exporters", self.exporters.join(","));
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Upnp {
+    pub enabled: bool,
+    pub location: String,
+    pub friendly_name: String,
+    pub discovery_timeout: u16,
+}
+
+impl Upnp {
+    fn init(conf: &Ini) -> Self {
+        let properties = conf.section(Some("UPnP"));
+        Self {
+            enabled: properties.get_bool("enabled"),
+            location: properties.get_string("location"),
+            friendly_name: properties.get_string("friendly_name"),
+            discovery_timeout: properties.get_u16_with_default("discovery_timeout", 3),
+        }
+    }
+    fn prepare_to_save(&self, ini: &mut Ini) {
+        ini.with_section(Some("UPnP"))
+            .set("enabled", bool_to_string(self.enabled))
+            .set("location", self.location.clone())
+            .set("friendly_name", self.friendly_name.clone())
+            .set("discovery_timeout", self.discovery_timeout.to_string());
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct OfflineSync {
+    pub enabled: bool,
+    pub favorites: bool,
+    pub albums: Vec<String>,
+    pub artists: Vec<String>,
+    pub playlists: Vec<String>,
+    pub max_kbps: u64,
+    pub window: String,
+    pub interval_hours: u64,
+}
+
+impl OfflineSync {
+    fn init(conf: &Ini) -> Self {
+        let properties = conf.section(Some("OfflineSync"));
+        Self {
+            enabled: properties.get_bool("enabled"),
+            favorites: properties.get_bool("favorites"),
+            albums: properties.get_list("albums"),
+            artists: properties.get_list("artists"),
+            playlists: properties.get_list("playlists"),
+            max_kbps: properties.get_u64_with_default("max_kbps", 0),
+            window: properties.get_string("window"),
+            interval_hours: properties.get_u64_with_default("interval_hours", 24),
+        }
+    }
+    fn prepare_to_save(&self, ini: &mut Ini) {
+        ini.with_section(Some("OfflineSync"))
+            .set("enabled", bool_to_string(self.enabled))
+            .set("favorites", bool_to_string(self.favorites))
+            .set("albums", self.albums.join(","))
+            .set("artists", self.artists.join(","))
+            .set("playlists", self.playlists.join(","))
+            .set("max_kbps", self.max_kbps.to_string())
+            .set("window", self.window.clone())
+            .set("interval_hours", self.interval_hours.to_string());
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Journal {
+    pub capacity: u64,
+    pub path: String,
+    pub record_elapsed: bool,
+}
+
+impl Journal {
+    fn init(conf: &Ini) -> Self {
+        let properties = conf.section(Some("Journal"));
+        Self {
+            capacity: properties.get_u64_with_default("capacity", 1000),
+            path: properties.get_string("path"),
+            record_elapsed: properties.get_bool_with_default("record_elapsed", false),
+        }
+    }
+    fn prepare_to_save(&self, ini: &mut Ini) {
+        ini.with_section(Some("Journal"))
+            .set("capacity", self.capacity.to_string())
+            .set("path", self.path.clone())
+            .set("record_elapsed", bool_to_string(self.record_elapsed));
+    }
+}
+
 #[derive(Debug)]
 #[derive(Clone)]
 pub struct Config {
@@ -179,8 +511,17 @@ pub struct Config {
     pub tidal: Tidal,
     pub player: Player,
     pub gui: Gui,
+    pub exporter_layout: ExporterLayout,
     pub exporter_file: ExporterFile,
     pub exporter_ftp: ExporterFTP,
+    pub exporter_webdav: ExporterWebDAV,
+    pub exporter_sftp: ExporterSFTP,
+    pub exporter_smb: ExporterSMB,
+    pub exporter_s3: ExporterS3,
+    pub transcoder: Transcoder,
+    pub upnp: Upnp,
+    pub offline_sync: OfflineSync,
+    pub journal: Journal,
 }
 
 impl Config {
@@ -196,17 +537,39 @@ impl Config {
             tidal: Tidal::init(&conf),
             player: Player::init(&conf),
             gui: Gui::init(&conf),
+            exporter_layout: ExporterLayout::init(&conf),
             exporter_file: ExporterFile::init(&conf),
             exporter_ftp: ExporterFTP::init(&conf),
+            exporter_webdav: ExporterWebDAV::init(&conf),
+            exporter_sftp: ExporterSFTP::init(&conf),
+            exporter_smb: ExporterSMB::init(&conf),
+            exporter_s3: ExporterS3::init(&conf),
+            transcoder: Transcoder::init(&conf),
+            upnp: Upnp::init(&conf),
+            offline_sync: OfflineSync::init(&conf),
+            journal: Journal::init(&conf),
         }
     }
-    pub fn save(&self) {
+    pub fn reload(&self) -> Self {
+        Self::init(self.path.clone())
+    }
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
         let mut conf = Ini::new();
         self.tidal.prepare_to_save(&mut conf);
         self.player.prepare_to_save(&mut conf);
         self.gui.prepare_to_save(&mut conf);
+        self.exporter_layout.prepare_to_save(&mut conf);
         self.exporter_file.prepare_to_save(&mut conf);
         self.exporter_ftp.prepare_to_save(&mut conf);
-        conf.write_to_file(self.path.clone()).unwrap();
+        self.exporter_webdav.prepare_to_save(&mut conf);
+        self.exporter_sftp.prepare_to_save(&mut conf);
+        self.exporter_smb.prepare_to_save(&mut conf);
+        self.exporter_s3.prepare_to_save(&mut conf);
+        self.transcoder.prepare_to_save(&mut conf);
+        self.upnp.prepare_to_save(&mut conf);
+        self.offline_sync.prepare_to_save(&mut conf);
+        self.journal.prepare_to_save(&mut conf);
+        conf.write_to_file(self.path.clone())?;
+        Ok(())
     }
 }
\ No newline at end of file
@@ -1,25 +1,255 @@
-use std::time::Duration;
+use std::{thread, time::Duration};
 use image::Rgb;
 use image::io::Reader;
+use log::{error, warn};
 use qrcode::QrCode;
-use slint::{Image, LogicalSize, Rgb8Pixel, SharedPixelBuffer, WindowSize};
-use crate::config::Config;
-use crate::state::{BackendState, Command, PlayerBus, PlayerStateCase};
+use slint::{Color, Image, LogicalSize, ModelRc, Rgb8Pixel, SharedPixelBuffer, VecModel, WindowSize};
+use crate::config::{self, Config};
+use crate::playlist::Palette;
+use crate::state::{BackendState, Command, Message, ModuleHealth, ModuleStatus, PlayerBus, PlayerStateCase, Severity, State, StateEventKind};
 
 slint::include_modules!();
 
+// Upper bound of a wait for the next state change.
+const IDLE_REFRESH: Duration = Duration::from_secs(1);
+// The progress bar is 600 px wide, finer steps are not visible.
+const PROGRESS_STEPS: f32 = 1000.0;
+// A toast hides by itself, the notification stays listed in the HTTP API.
+const TOAST_DURATION: chrono::TimeDelta = chrono::TimeDelta::new(8, 0).unwrap();
+// White on dark, as before covers had palettes.
+const DEFAULT_PALETTE: Palette = Palette { dominant: [0, 0, 0], vibrant: [255, 255, 255], muted: [0, 0, 0], text: [255, 255, 255], shadow: [0, 0, 0] };
+
 pub struct Gui {
     config: Config,
     player_bus: PlayerBus,
     ui: AppWindow,
 }
 
+const PLACEHOLDER_COLORS: [(u8, u8, u8); 6] = [(0x5b, 0x3a, 0x29), (0x2f, 0x4f, 0x4f), (0x4a, 0x3b, 0x5c), (0x3d, 0x5a, 0x3b), (0x5c, 0x2e, 0x3f), (0x2e, 0x41, 0x5c)];
+
+// First letters of the first two words of the main artist, e.g. "Daft Punk, Pharrell" -> "DP".
+fn artist_initials(artist_name: &str) -> String {
+    artist_name.split(',').next().unwrap_or_default()
+        .split_whitespace()
+        .filter_map(|word| word.chars().next())
+        .take(2)
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+// Same artist, same color.
+fn placeholder_color(artist_name: &str) -> Color {
+    let hash = artist_name.bytes().fold(0usize, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as usize));
+    let (red, green, blue) = PLACEHOLDER_COLORS[hash % PLACEHOLDER_COLORS.len()];
+    Color::from_rgb_u8(red, green, blue)
+}
+
 fn duration_formated(duration: &Duration) -> String {
     let seconds = duration.as_secs() % 60;
     let minutes = (duration.as_secs() / 60) % 60;
     format!("{minutes}:{seconds:0>2}")
 }
 
+fn color([red, green, blue]: [u8; 3]) -> Color {
+    Color::from_rgb_u8(red, green, blue)
+}
+
+fn severity_color(severity: Severity) -> Color {
+    match severity {
+        Severity::Info => Color::from_rgb_u8(0x2e, 0x41, 0x5c),
+        Severity::Warning => Color::from_rgb_u8(0x8a, 0x6d, 0x1f),
+        Severity::Error => Color::from_rgb_u8(0x8b, 0x2e, 0x2e),
+    }
+}
+
+fn module_row(module: &ModuleHealth) -> ModuleRow {
+    let status = match &module.status {
+        ModuleStatus::Running => "running".to_string(),
+        ModuleStatus::Restarting(reason) => format!("restarting, {}", reason),
+        ModuleStatus::Finished => "finished".to_string(),
+    };
+    ModuleRow {
+        name: module.name.clone().into(),
+        status: status.into(),
+        restarts: module.restarts as i32,
+        healthy: !matches!(module.status, ModuleStatus::Restarting(_)),
+    }
+}
+
+#[derive(Clone, PartialEq)]
+struct Toast {
+    id: u64,
+    severity: Severity,
+    text: String,
+}
+
+// Everything the window shows, built from the state on the worker thread and compared with the previous one,
+// so the event loop only receives properties that changed.
+#[derive(Clone, PartialEq)]
+struct GuiModel {
+    is_loading: bool,
+    is_offline: bool,
+    login_link: Option<String>,
+    track_name: String,
+    artist_name: String,
+    album_name: String,
+    track_duration: String,
+    duration: String,
+    duration_percentage: f32,
+    cover_foreground: Option<String>,
+    cover_background: Option<String>,
+    palette: Option<Palette>,
+    toast: Option<Toast>,
+    modules: Vec<ModuleHealth>,
+}
+
+impl GuiModel {
+    fn build(state: &State) -> Self {
+        let track = state.track.as_ref();
+        let track_duration = track.map(|track| track.duration).unwrap_or(Duration::ZERO);
+        let duration = state.player.playing_time.unwrap_or(Duration::ZERO);
+        let percentage = if track_duration.is_zero() { 0.0 } else { duration.as_secs_f32() / track_duration.as_secs_f32() };
+
+        Self {
+            is_loading: matches!(state.player.case, PlayerStateCase::Loading),
+            is_offline: state.offline.enabled(),
+            login_link: match &state.backends.tidal {
+                BackendState::WaitingForLoginByLink(login_link) => Some(login_link.clone()),
+                _ => None,
+            },
+            track_name: track.map(|track| track.title.clone()).unwrap_or("Loading...".to_string()),
+            artist_name: track.map(|track| track.artist_name.clone()).unwrap_or_default(),
+            album_name: track.map(|track| track.album_name.clone()).unwrap_or_default(),
+            track_duration: duration_formated(&track_duration),
+            duration: duration_formated(&duration),
+            duration_percentage: (percentage * PROGRESS_STEPS).round() / PROGRESS_STEPS,
+            cover_foreground: track.and_then(|track| track.cover.foreground.clone()),
+            cover_background: track.and_then(|track| track.cover.background.clone()),
+            palette: track.and_then(|track| track.cover.palette),
+            toast: state.notifications.latest_active()
+                .filter(|notification| chrono::Local::now() - notification.time < TOAST_DURATION)
+                .map(|notification| Toast {
+                    id: notification.id,
+                    severity: notification.severity,
+                    text: format!("{}: {}", notification.source, notification.message),
+                }),
+            modules: state.modules.clone(),
+        }
+    }
+}
+
+fn changed<T: PartialEq>(previous: Option<&GuiModel>, next: &GuiModel, field: impl Fn(&GuiModel) -> &T) -> bool {
+    previous.is_none_or(|previous| field(previous) != field(next))
+}
+
+// Decoded images are prepared off the event loop, only the pixel buffers are handed over.
+#[derive(Default)]
+struct DecodedImages {
+    foreground: Option<SharedPixelBuffer<Rgb8Pixel>>,
+    background: Option<SharedPixelBuffer<Rgb8Pixel>>,
+    qrcode: Option<SharedPixelBuffer<Rgb8Pixel>>,
+}
+
+fn decode_cover(path: &str) -> Option<SharedPixelBuffer<Rgb8Pixel>> {
+    let image = Reader::open(path).ok()?
+        .with_guessed_format().ok()?
+        .decode()
+        .inspect_err(|err| warn!("[GUI] cover {} decode error: {:?}", path, err))
+        .ok()?
+        .to_rgb8();
+
+    Some(SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(image.as_raw(), image.width(), image.height()))
+}
+
+fn render_qrcode(login_link: &str) -> Option<SharedPixelBuffer<Rgb8Pixel>> {
+    let qrcode = QrCode::new(login_link).inspect_err(|err| error!("[GUI] login QR code error: {:?}", err)).ok()?;
+    let image = qrcode.render::<Rgb<u8>>().build();
+    Some(SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(image.as_raw(), image.width(), image.height()))
+}
+
+fn decode_images(previous: Option<&GuiModel>, next: &GuiModel) -> DecodedImages {
+    let mut images = DecodedImages::default();
+
+    if changed(previous, next, |model| &model.cover_foreground) {
+        images.foreground = next.cover_foreground.as_deref().and_then(decode_cover);
+    }
+    if changed(previous, next, |model| &model.cover_background) {
+        images.background = next.cover_background.as_deref().and_then(decode_cover);
+    }
+    if changed(previous, next, |model| &model.login_link) {
+        images.qrcode = next.login_link.as_deref().and_then(render_qrcode);
+    }
+    images
+}
+
+fn apply(data: &Data, gui_config: &config::Gui, previous: Option<&GuiModel>, next: &GuiModel, images: DecodedImages) {
+    let image = |buffer: Option<SharedPixelBuffer<Rgb8Pixel>>| buffer.map(Image::from_rgb8).unwrap_or_default();
+
+    if changed(previous, next, |model| &model.is_loading) {
+        data.set_is_loading(next.is_loading);
+    }
+    if changed(previous, next, |model| &model.is_offline) {
+        data.set_is_offline(next.is_offline);
+    }
+    if changed(previous, next, |model| &model.login_link) {
+        data.set_is_session_exist(next.login_link.is_none());
+        data.set_session_code(next.login_link.clone().unwrap_or_default().into());
+        data.set_session_qrcode(image(images.qrcode));
+    }
+
+    if changed(previous, next, |model| &model.track_name) {
+        data.set_current_track_name(next.track_name.clone().into());
+    }
+    if changed(previous, next, |model| &model.artist_name) {
+        data.set_current_artist_initials(artist_initials(&next.artist_name).into());
+        data.set_placeholder_color(placeholder_color(&next.artist_name));
+        data.set_current_artist_name(next.artist_name.clone().into());
+    }
+    if changed(previous, next, |model| &model.album_name) {
+        data.set_current_album_name(next.album_name.clone().into());
+    }
+
+    if changed(previous, next, |model| &model.track_duration) {
+        data.set_current_track_duration(next.track_duration.clone().into());
+    }
+    if changed(previous, next, |model| &model.duration) {
+        data.set_current_duration(next.duration.clone().into());
+    }
+    if changed(previous, next, |model| &model.duration_percentage) {
+        data.set_current_duration_percentage(next.duration_percentage);
+    }
+
+    if changed(previous, next, |model| &model.palette) {
+        let palette = next.palette.unwrap_or(DEFAULT_PALETTE);
+        data.set_text_color(color(palette.text));
+        data.set_shadow_color(color(palette.shadow));
+        data.set_accent_color(color(palette.vibrant));
+        data.set_muted_color(color(palette.muted));
+    }
+
+    if changed(previous, next, |model| &model.toast) {
+        data.set_toast_visible(next.toast.is_some());
+        if let Some(toast) = &next.toast {
+            data.set_toast_id(toast.id as i32);
+            data.set_toast_text(toast.text.clone().into());
+            data.set_toast_color(severity_color(toast.severity));
+        }
+    }
+
+    if changed(previous, next, |model| &model.modules) {
+        let rows: Vec<ModuleRow> = next.modules.iter().map(module_row).collect();
+        data.set_modules(ModelRc::new(VecModel::from(rows)));
+    }
+
+    if changed(previous, next, |model| &model.cover_background) {
+        data.set_current_cover_background(image(images.background));
+    }
+    if changed(previous, next, |model| &model.cover_foreground) {
+        data.set_has_cover_foreground(next.cover_foreground.is_some() || !gui_config.display_cover_foreground);
+        data.set_current_cover_foreground(image(images.foreground));
+    }
+}
+
 impl Gui {
     pub fn init(config: Config, player_bus: PlayerBus) -> Gui {
         let ui = AppWindow::new().unwrap();
@@ -28,100 +258,55 @@ impl Gui {
     pub fn gui_loop(&mut self) {
         let gui_config = self.config.gui.clone();
         self.ui.window().set_size(WindowSize::Logical(LogicalSize::new(gui_config.window_x as f32, gui_config.window_y as f32)));
+        self.ui.global::<Data>().set_window_x(gui_config.window_x as i32);
+        self.ui.global::<Data>().set_window_y(gui_config.window_y as i32);
 
-        let main_window_weak = self.ui.as_weak();
-        let bus = self.player_bus.clone();
-
-        let request_next_bus = bus.clone();
+        let request_next_bus = self.player_bus.clone();
         self.ui.global::<Data>().on_request_next_track(move || {
             request_next_bus.publish_command(Command::Next);
         });
 
-        self.ui.global::<Data>().on_request_new_value(move || {
-            let current_state = bus.read_state().clone();
-
-            let is_loading = match current_state.player.case {
-                PlayerStateCase::Loading => true,
-                _ => false,
-            };
-
-            let current_track_name = current_state.track.clone().map( |track| track.title).unwrap_or("Loading...".to_string());
-            let current_artist_name = current_state.track.clone().map( |track| track.artist_name).unwrap_or("".to_string());
-            let current_album_name = current_state.track.clone().map( |track| track.album_name).unwrap_or("".to_string());
-
-            let current_cover_foreground = current_state.track.clone().map(|track| track.cover.foreground).flatten();
-            let current_cover_background = current_state.track.clone().map(|track| track.cover.background).flatten();
-
-            let current_track_duration = &current_state.track.clone().map( |track| track.duration).unwrap_or(Duration::ZERO);
-            let current_duration = &current_state.player.playing_time.unwrap_or(Duration::ZERO);
-
-            if let Some(handle) = main_window_weak.upgrade() {
-                handle.global::<Data>().set_window_x(gui_config.window_x as i32);
-                handle.global::<Data>().set_window_y(gui_config.window_y as i32);
-                handle.global::<Data>().set_is_loading(is_loading);
-
-                handle.global::<Data>().set_current_track_name(current_track_name.into());
-                handle.global::<Data>().set_current_artist_name(current_artist_name.into());
-                handle.global::<Data>().set_current_album_name(current_album_name.into());
-
-                handle.global::<Data>().set_current_track_duration(duration_formated(current_track_duration).into());
-                handle.global::<Data>().set_current_duration(duration_formated(current_duration).into());
-                handle.global::<Data>().set_current_duration_percentage(current_duration.as_secs_f32() / current_track_duration.as_secs_f32());
-
-                if let Some(cover) = current_cover_background {
-                    let image = Reader::open(cover.as_str()).unwrap()
-                        .with_guessed_format().unwrap()
-                        .decode().unwrap();
-
-                    let buffer = SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(
-                        image.as_rgb8().expect("Fault image to buffer!"),
-                        image.width(),
-                        image.height()
-                    );
-                    handle.global::<Data>().set_current_cover_background(Image::from_rgb8(buffer));
-                } else {
-                    handle.global::<Data>().set_current_cover_background(Image::default());
-                }
+        let dismiss_bus = self.player_bus.clone();
+        self.ui.global::<Data>().on_dismiss_notification(move |id| {
+            dismiss_bus.publish_message(Message::UserDismissNotification(id as u64));
+        });
 
-                if let Some(cover) = current_cover_foreground {
-                    let image = Reader::open(cover.as_str()).unwrap()
-                        .with_guessed_format().unwrap()
-                        .decode().unwrap();
-
-                    let buffer = SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(
-                        image.as_rgb8().expect("Fault image to buffer!"),
-                        image.width(),
-                        image.height()
-                    );
-                    handle.global::<Data>().set_current_cover_foreground(Image::from_rgb8(buffer));
-                } else {
-                    handle.global::<Data>().set_current_cover_foreground(Image::default());
-                }
+        Self::start_update_worker(self.ui.as_weak(), self.player_bus.clone(), gui_config);
+
+        self.ui.run().unwrap();
+    }
 
-                match current_state.backends.tidal {
-                    BackendState::WaitingForLoginByLink(login_link) => {
-                        handle.global::<Data>().set_is_session_exist(false);
-                        handle.global::<Data>().set_session_code(login_link.clone().into());
+    fn start_update_worker(main_window_weak: slint::Weak<AppWindow>, bus: PlayerBus, gui_config: config::Gui) {
+        let subscription = bus.subscribe(StateEventKind::all());
 
-                        let qrcode = QrCode::new(login_link).unwrap();
-                        let image = qrcode.render::<Rgb<u8>>().build();
-                        let pixel_buffer = SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(
-                            image.as_raw(),
-                            image.width(),
-                            image.height(),
-                        );
+        thread::spawn(move || {
+            let mut previous: Option<GuiModel> = None;
 
-                        handle.global::<Data>().set_session_qrcode(Image::from_rgb8(pixel_buffer));
-                    }
-                    _ => {
-                        handle.global::<Data>().set_is_session_exist(true);
-                        handle.global::<Data>().set_session_code(String::new().into());
-                        handle.global::<Data>().set_session_qrcode(Image::default());
+            loop {
+                let next = GuiModel::build(&bus.read_state());
+                if previous.as_ref() != Some(&next) {
+                    let images = decode_images(previous.as_ref(), &next);
+                    let window = main_window_weak.clone();
+                    let gui_config = gui_config.clone();
+                    let (applied_previous, applied_next) = (previous.clone(), next.clone());
+
+                    let result = slint::invoke_from_event_loop(move || {
+                        if let Some(handle) = window.upgrade() {
+                            apply(&handle.global::<Data>(), &gui_config, applied_previous.as_ref(), &applied_next, images);
+                        }
+                    });
+                    if result.is_err() {
+                        warn!("[GUI] event loop stopped, update worker exits");
+                        return;
                     }
+                    previous = Some(next);
+                }
+
+                // A burst of events is rendered once, from the latest state.
+                if subscription.recv_timeout(IDLE_REFRESH).is_some() {
+                    while subscription.try_recv().is_some() {}
                 }
             }
         });
-
-        self.ui.run().unwrap();
     }
-}
\ No newline at end of file
+}
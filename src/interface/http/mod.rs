@@ -1,8 +1,8 @@
 use log::info;
-use serde_json::Value;
-use tiny_http::{Response, Server};
+use serde_json::{json, Value};
+use tiny_http::{Header, Request, Response, Server};
 
-use crate::state::{self, PlayerBus};
+use crate::state::{self, ModuleStatus, PlayerBus};
 
 pub fn server(player_bus: &PlayerBus) {
     let server = Server::http("0.0.0.0:8001").unwrap();
@@ -10,6 +10,7 @@ pub fn server(player_bus: &PlayerBus) {
     for mut request in server.incoming_requests() {
         if request.method().eq(&tiny_http::Method::Post) {
             info!("[Server control] {}", request.url());
+            let mut status = 200;
 
             match request.url() {
                 "/action/next" => player_bus.publish_message(state::Message::UserPlayNext),
@@ -36,12 +37,94 @@ pub fn server(player_bus: &PlayerBus) {
                         player_bus.publish_message(state::Message::UserPlayArtist(id.to_string()));
                     }
                 },
+                "/action/offline" => match json_body(&mut request).and_then(|body| body["enabled"].as_bool()) {
+                    Some(enabled) => player_bus.publish_message(state::Message::UserSwitchOfflineMode(enabled)),
+                    None => status = 400,
+                },
+                "/action/dismiss_notification" => match json_body(&mut request).and_then(|body| body["id"].as_u64()) {
+                    Some(id) => player_bus.publish_message(state::Message::UserDismissNotification(id)),
+                    None => status = 400,
+                },
+                "/action/available_offline" => match json_body(&mut request).and_then(|body| body["url"].as_str().map(str::to_string)) {
+                    Some(url) => player_bus.publish_message(state::Message::UserMarkAvailableOffline(url)),
+                    None => status = 400,
+                },
                 _ => {}
             }
 
-            let _ = request.respond(Response::empty(200));
+            let _ = request.respond(Response::empty(status));
+        } else if request.method().eq(&tiny_http::Method::Get) && request.url() == "/offline_sync" {
+            let offline_sync = player_bus.read_state().offline_sync;
+            let body = json!({
+                "running": offline_sync.running,
+                "total": offline_sync.total,
+                "done": offline_sync.done,
+                "failed": offline_sync.failed,
+                "current": offline_sync.current,
+                "last_finished": offline_sync.last_finished.map(|date| date.to_rfc3339()),
+            });
+
+            let _ = request.respond(Response::from_string(body.to_string())
+                .with_header(Header::from_bytes("Content-Type", "application/json").unwrap()));
+        } else if request.method().eq(&tiny_http::Method::Get) && request.url() == "/notifications" {
+            let notifications: Vec<Value> = player_bus.read_state().notifications.items.iter().rev()
+                .map(|notification| json!({
+                    "id": notification.id,
+                    "severity": notification.severity,
+                    "source": notification.source,
+                    "message": notification.message,
+                    "time": notification.time.to_rfc3339(),
+                    "dismissed": notification.dismissed,
+                }))
+                .collect();
+
+            let _ = request.respond(Response::from_string(Value::from(notifications).to_string())
+                .with_header(Header::from_bytes("Content-Type", "application/json").unwrap()));
+        } else if request.method().eq(&tiny_http::Method::Get) && request.url() == "/health" {
+            let modules: Vec<Value> = player_bus.read_state().modules.iter()
+                .map(|module| {
+                    let (status, reason) = match &module.status {
+                        ModuleStatus::Running => ("running", None),
+                        ModuleStatus::Restarting(reason) => ("restarting", Some(reason.clone())),
+                        ModuleStatus::Finished => ("finished", None),
+                    };
+                    json!({
+                        "name": module.name,
+                        "status": status,
+                        "reason": reason,
+                        "restarts": module.restarts,
+                        "last_restart": module.last_restart.map(|date| date.to_rfc3339()),
+                    })
+                })
+                .collect();
+
+            let _ = request.respond(Response::from_string(Value::from(modules).to_string())
+                .with_header(Header::from_bytes("Content-Type", "application/json").unwrap()));
+        } else if request.method().eq(&tiny_http::Method::Get) && request.url() == "/journal" {
+            let body = serde_json::to_string(&player_bus.journal().recent()).unwrap_or_default();
+
+            let _ = request.respond(Response::from_string(body)
+                .with_header(Header::from_bytes("Content-Type", "application/json").unwrap()));
+        } else if request.method().eq(&tiny_http::Method::Get) && request.url() == "/commands" {
+            let routes: Vec<Value> = player_bus.command_routes().iter()
+                .map(|route| json!({
+                    "commands": route.commands.iter().map(|command| format!("{:?}", command)).collect::<Vec<String>>(),
+                    "delivered": route.delivered,
+                    "pending": route.pending,
+                }))
+                .collect();
+
+            let _ = request.respond(Response::from_string(Value::from(routes).to_string())
+                .with_header(Header::from_bytes("Content-Type", "application/json").unwrap()));
         } else {
             let _ = request.respond(Response::empty(404));
         }
     }
-}
\ No newline at end of file
+}
+
+// None for an unreadable body or invalid JSON, the client gets 400 instead of the server stopping.
+fn json_body(request: &mut Request) -> Option<Value> {
+    let mut content = String::new();
+    request.as_reader().read_to_string(&mut content).ok()?;
+    serde_json::from_str(&content).ok()
+}
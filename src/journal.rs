@@ -0,0 +1,247 @@
+use std::{collections::VecDeque, error::Error, fs::{self, File, OpenOptions}, io::Write, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}};
+
+use chrono::Local;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::playlist::BufferedTrack;
+use crate::state::{Command, Message, PlayerBus};
+use crate::util::lock;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalEvent {
+    Message(Box<Message>),
+    Command(Command),
+}
+
+impl JournalEvent {
+    // Entries outlive the tracks, keeping their audio would pin every recorded FLAC in memory.
+    fn without_audio(self) -> Self {
+        match self {
+            JournalEvent::Message(message) => JournalEvent::Message(Box::new(match *message {
+                Message::PlayerPlayingNewTrack(track) => Message::PlayerPlayingNewTrack(track.without_stream()),
+                Message::TrackDiscoveredLocally(track) => Message::TrackDiscoveredLocally(track.without_stream()),
+                message => message,
+            })),
+            JournalEvent::Command(Command::AddBufferedTracksToPlaylist(tracks)) => JournalEvent::Command(Command::AddBufferedTracksToPlaylist(
+                tracks.into_iter().map(BufferedTrack::without_stream).collect()
+            )),
+            event => event,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub time: String,
+    pub event: JournalEvent,
+}
+
+// Everything published on the PlayerBus, the last entries in memory and optionally all of them in a JSONL file.
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Journal {
+    entries: Arc<Mutex<VecDeque<JournalEntry>>>,
+    capacity: usize,
+    record_elapsed: bool,
+    file: Option<Arc<Mutex<File>>>,
+    queue_empty: Arc<AtomicBool>,
+}
+
+impl Journal {
+    pub fn init(config: &config::Journal) -> Self {
+        let file = match config.path.as_str() {
+            "" => None,
+            path => OpenOptions::new().create(true).append(true).open(path)
+                .inspect(|_| info!("[Journal] writing to {}", path))
+                .inspect_err(|err| warn!("[Journal] can not open {}: {:?}", path, err))
+                .ok()
+                .map(|file| Arc::new(Mutex::new(file))),
+        };
+
+        Self {
+            entries: Arc::new(Mutex::new(VecDeque::new())),
+            capacity: config.capacity as usize,
+            record_elapsed: config.record_elapsed,
+            file,
+            queue_empty: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn in_memory(capacity: usize) -> Self {
+        Self { entries: Arc::new(Mutex::new(VecDeque::new())), capacity, record_elapsed: true, file: None, queue_empty: Arc::new(AtomicBool::new(false)) }
+    }
+
+    pub fn record(&self, event: JournalEvent) {
+        // The player reports elapsed time every 50 ms, it would push everything else out of the buffer.
+        if !self.record_elapsed && matches!(&event, JournalEvent::Message(message) if matches!(**message, Message::PlayerElapsed(_))) {
+            return;
+        }
+        // The player repeats it every 200 ms while waiting for tracks, only the first one is kept.
+        let queue_empty = matches!(&event, JournalEvent::Message(message) if matches!(**message, Message::PlayerQueueIsEmpty));
+        if self.queue_empty.swap(queue_empty, Ordering::Relaxed) && queue_empty {
+            return;
+        }
+
+        let entry = JournalEntry { time: Local::now().to_rfc3339(), event: event.without_audio() };
+
+        if let Some(file) = &self.file {
+            let written = serde_json::to_string(&entry)
+                .map_err(|err| err.to_string())
+                .and_then(|line| writeln!(lock(file), "{}", line).map_err(|err| err.to_string()));
+            if let Err(err) = written {
+                warn!("[Journal] write error: {}", err);
+            }
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = lock(&self.entries);
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    pub fn recent(&self) -> Vec<JournalEntry> {
+        lock(&self.entries).iter().cloned().collect()
+    }
+
+    pub fn read_file(path: &str) -> Result<Vec<JournalEntry>, Box<dyn Error>> {
+        fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|err| err.into()))
+            .collect()
+    }
+}
+
+// Feeds the recorded messages into a fresh bus, commands are not replayed since the bus issues them again.
+pub fn replay(entries: &[JournalEntry], mut on_message: impl FnMut(&Message, &PlayerBus)) -> PlayerBus {
+    let player_bus = PlayerBus::new(Journal::in_memory(entries.len()));
+
+    for entry in entries {
+        if let JournalEvent::Message(message) = &entry.event {
+            player_bus.publish_message(*message.clone());
+            on_message(message, &player_bus);
+        }
+    }
+    player_bus
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use super::{replay, Journal, JournalEntry, JournalEvent};
+    use crate::playlist::{BufferedTrack, Cover, Track, TrackMetadata};
+    use crate::state::{Command, Message, PlayerStateCase};
+
+    fn buffered_track(id: &str) -> BufferedTrack {
+        BufferedTrack {
+            track: Track {
+                id: id.to_string(),
+                title: "Title".to_string(),
+                artist_name: "Artist".to_string(),
+                album_name: "Album".to_string(),
+                album_image: "".to_string(),
+                duration: Duration::from_secs(180),
+                metadata: TrackMetadata::default(),
+            },
+            stream: Bytes::from_static(b"fLaC"),
+            cover: Cover::empty(),
+        }
+    }
+
+    fn message(message: Message) -> JournalEntry {
+        JournalEntry { time: "2024-01-01T00:00:00+00:00".to_string(), event: JournalEvent::Message(Box::new(message)) }
+    }
+
+    #[test]
+    fn replay_paused_track() {
+        let entries = vec![
+            message(Message::PlayerPlayingNewTrack(buffered_track("1"))),
+            message(Message::PlayerElapsed(Duration::from_secs(12))),
+            message(Message::PlayerToPause),
+        ];
+
+        let mut replayed = 0;
+        let state = replay(&entries, |_, _| replayed += 1).read_state();
+
+        assert_eq!(replayed, 3);
+        assert_eq!(state.track.map(|track| track.id), Some("1".to_string()));
+        assert_eq!(state.player.case, PlayerStateCase::Paused);
+        assert_eq!(state.player.playing_time, Some(Duration::from_secs(12)));
+    }
+
+    #[test]
+    fn replay_from_serialized_journal() {
+        let journal = Journal::in_memory(10);
+        journal.record(JournalEvent::Message(Box::new(Message::PlayerPlayingNewTrack(buffered_track("1")))));
+        journal.record(JournalEvent::Message(Box::new(Message::PlayerPlayingNewTrack(buffered_track("2")))));
+        journal.record(JournalEvent::Message(Box::new(Message::PlayerQueueIsEmpty)));
+
+        let lines: Vec<String> = journal.recent().iter().map(|entry| serde_json::to_string(entry).unwrap()).collect();
+        let entries: Vec<JournalEntry> = lines.iter().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let state = replay(&entries, |_, _| {}).read_state();
+
+        assert!(state.track.is_none());
+        assert_eq!(state.player.case, PlayerStateCase::Loading);
+    }
+
+    #[test]
+    fn replay_starting_mid_session() {
+        let entries = vec![
+            message(Message::UserLike),
+            message(Message::UserLoadRadio),
+            message(Message::PlayerPlayingNewTrack(buffered_track("2"))),
+        ];
+
+        let player_bus = replay(&entries, |_, _| {});
+
+        let issued = player_bus.journal().recent().into_iter()
+            .filter(|entry| matches!(entry.event, JournalEvent::Command(Command::Like(_) | Command::Radio(_))))
+            .count();
+        assert_eq!(issued, 0);
+        assert_eq!(player_bus.read_state().track.map(|track| track.id), Some("2".to_string()));
+    }
+
+    #[test]
+    fn audio_is_not_kept() {
+        let journal = Journal::in_memory(10);
+        journal.record(JournalEvent::Message(Box::new(Message::TrackDiscoveredLocally(buffered_track("1")))));
+        journal.record(JournalEvent::Command(Command::AddBufferedTracksToPlaylist(vec![buffered_track("1")])));
+
+        for entry in journal.recent() {
+            match entry.event {
+                JournalEvent::Message(message) => match *message {
+                    Message::TrackDiscoveredLocally(track) => assert!(track.stream.is_empty()),
+                    message => panic!("unexpected message {:?}", message),
+                },
+                JournalEvent::Command(Command::AddBufferedTracksToPlaylist(tracks)) => assert!(tracks[0].stream.is_empty()),
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+    }
+
+    #[test]
+    fn repeated_empty_queue_is_coalesced() {
+        let journal = Journal::in_memory(10);
+        journal.record(JournalEvent::Message(Box::new(Message::PlayerQueueIsEmpty)));
+        journal.record(JournalEvent::Message(Box::new(Message::PlayerQueueIsEmpty)));
+        journal.record(JournalEvent::Message(Box::new(Message::PlayerQueueIsEmpty)));
+        journal.record(JournalEvent::Message(Box::new(Message::PlayerPlayingNewTrack(buffered_track("1")))));
+        journal.record(JournalEvent::Message(Box::new(Message::PlayerQueueIsEmpty)));
+
+        assert_eq!(journal.recent().len(), 3);
+    }
+}
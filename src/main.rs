@@ -2,13 +2,16 @@ use backend::BackendInitialization;
 use env_logger::Target;
 use interface::gui::Gui;
 
-use log::error;
-use thread_priority::{ThreadBuilderExt, ThreadPriority};
-use std::thread::{self, JoinHandle};
+use log::{error, info};
+use thread_priority::ThreadPriority;
+use std::thread::JoinHandle;
 
 mod state;
 use state::PlayerBus;
 
+mod journal;
+use journal::Journal;
+
 mod playlist;
 use playlist::Playlist;
 
@@ -20,17 +23,22 @@ use config::Config;
 mod player;
 mod interface;
 
+mod supervisor;
+use supervisor::Supervisor;
+
+mod util;
+
 use interface::http;
 
-fn service_module(backend_init: BackendInitialization, playlist: Playlist) {
-    thread::spawn(move || {
+fn service_module(supervisor: &Supervisor, backend_init: BackendInitialization, playlist: Playlist) {
+    supervisor.spawn("Service", None, move || {
         backend_init.initialization();
-        backend_init.get_initialized().listen_commands(playlist);
+        backend_init.get_initialized().listen_commands(playlist.clone());
     });
 }
 
-fn downloader_module(playlist: Playlist, backend_init: BackendInitialization) {
-    thread::spawn(move || {
+fn downloader_module(supervisor: &Supervisor, playlist: Playlist, backend_init: BackendInitialization) {
+    supervisor.spawn("Downloader", None, move || {
         let backend = backend_init.get_initialized();
         backend.discover();
 
@@ -44,20 +52,24 @@ fn downloader_module(playlist: Playlist, backend_init: BackendInitialization) {
     });
 }
 
-fn server_module(player_bus: PlayerBus) {
-    thread::Builder::new()
-        .name("Server module".to_owned())
-        .spawn_with_priority(ThreadPriority::Min, move |_| {
-            http::server(&player_bus);
-    }).unwrap();
+fn offline_sync_module(supervisor: &Supervisor, backend_init: BackendInitialization) {
+    supervisor.spawn("Offline sync", Some(ThreadPriority::Min), move || {
+        if let Some(offline_sync) = backend_init.offline_sync() {
+            offline_sync.run();
+        }
+    });
+}
+
+fn server_module(supervisor: &Supervisor, player_bus: PlayerBus) {
+    supervisor.spawn("Server", Some(ThreadPriority::Min), move || {
+        http::server(&player_bus);
+    });
 }
 
-fn player_module(playlist: Playlist, player_bus: PlayerBus) -> JoinHandle<()> {
-    thread::Builder::new()
-        .name("Player module".to_owned())
-        .spawn_with_priority(ThreadPriority::Max, move |_| {
-            player::player(&playlist, player_bus);
-    }).unwrap()
+fn player_module(supervisor: &Supervisor, playlist: Playlist, player_bus: PlayerBus) -> JoinHandle<()> {
+    supervisor.spawn("Player", Some(ThreadPriority::Max), move || {
+        player::player(&playlist, player_bus.clone());
+    })
 }
 
 fn gui_module(config: Config, player_bus: PlayerBus) {
@@ -65,6 +77,25 @@ fn gui_module(config: Config, player_bus: PlayerBus) {
         .gui_loop()
 }
 
+fn replay_journal(path: Option<String>) {
+    let Some(path) = path else {
+        error!("Usage: replay-journal <journal.jsonl>");
+        return;
+    };
+
+    match Journal::read_file(&path) {
+        Ok(entries) => {
+            info!("[Journal] replaying {} entries from {}", entries.len(), path);
+            let player_bus = journal::replay(&entries, |message, player_bus| {
+                let state = player_bus.read_state();
+                info!("[Journal] {:?} -> player: {:?}, track: {:?}, backends: {:?}, offline: {:?}", message, state.player, state.track.map(|track| track.id), state.backends, state.offline);
+            });
+            info!("[Journal] final state: {:?}", player_bus.read_state());
+        },
+        Err(err) => error!("[Journal] can not read {}: {:?}", path, err),
+    }
+}
+
 fn main() {
     env_logger::Builder::from_default_env()
         .target(Target::Stdout)
@@ -72,16 +103,30 @@ fn main() {
         .init();
 
     let config = Config::init_default_path();
+
+    if let Some(command) = std::env::args().nth(1) {
+        match command.as_str() {
+            "migrate-cache" => backend::migrate_cache(&config),
+            "scrub-cache" => backend::scrub_cache(&config),
+            "replay-journal" => replay_journal(std::env::args().nth(2)),
+            _ => error!("Unknown command: {}, available: migrate-cache, scrub-cache, replay-journal", command),
+        }
+        return;
+    }
+
     let playlist = Playlist::new();
-    let player_bus = PlayerBus::new();
+    let player_bus = PlayerBus::new(Journal::init(&config.journal));
 
     let backend_init = BackendInitialization::new(config.clone(), player_bus.clone());
 
-    service_module(backend_init.clone(), playlist.clone());
-    downloader_module(playlist.clone(), backend_init.clone());
-    server_module(player_bus.clone());
+    let supervisor = Supervisor::new(player_bus.clone());
+
+    service_module(&supervisor, backend_init.clone(), playlist.clone());
+    downloader_module(&supervisor, playlist.clone(), backend_init.clone());
+    offline_sync_module(&supervisor, backend_init.clone());
+    server_module(&supervisor, player_bus.clone());
 
-    let player = player_module(playlist.clone(), player_bus.clone());
+    let player = player_module(&supervisor, playlist.clone(), player_bus.clone());
 
     if config.gui.enabled {
         gui_module(config.clone(), player_bus.clone());
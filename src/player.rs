@@ -2,7 +2,7 @@ use rodio::{OutputStream, Decoder, Sink, Source};
 use std::{io::{BufReader, Cursor}, thread, time::{Duration, Instant}};
 use log::{debug, error};
 
-use crate::{state::{Command, Message, PlayerBus}, playlist::{BufferedTrack, Playlist}};
+use crate::{state::{Command, CommandKind, Message, Notice, PlayerBus}, playlist::{BufferedTrack, Playlist}};
 
 fn retry<T, E>(function: fn() -> Result<T, E>) -> T where E: std::fmt::Display {
     match function() {
@@ -15,7 +15,7 @@ fn retry<T, E>(function: fn() -> Result<T, E>) -> T where E: std::fmt::Display {
     }
 }
 
-fn source(track: BufferedTrack) -> Option<Decoder<BufReader<std::io::Cursor<bytes::Bytes>>>> {
+fn source(track: BufferedTrack, player_bus: &PlayerBus) -> Option<Decoder<BufReader<std::io::Cursor<bytes::Bytes>>>> {
     let source_result = Decoder::new_flac(BufReader::with_capacity(4_194_304, Cursor::new(track.stream)));
 
     match source_result {
@@ -25,13 +25,17 @@ fn source(track: BufferedTrack) -> Option<Decoder<BufReader<std::io::Cursor<byte
         },
         Err(err) => {
             error!("[Player] Audio file '{:?}' decode error, try next...", err);
+            player_bus.publish_message(Message::NotificationRaised(Notice::error("Player", format!("{} can not be played: {}", track.track.full_name(), err))));
             None
         },
     }
 }
 
+// How often the elapsed time is published while playing.
+const ELAPSED_TICK: Duration = Duration::from_millis(50);
+
 pub fn player(playlist: &Playlist, mut player_bus: PlayerBus) {
-    let command_channel = player_bus.register_command_channel(vec![Command::Play.as_string(), Command::Pause.as_string(), Command::Next.as_string()]);
+    let command_channel = player_bus.register_command_channel(vec![CommandKind::Play, CommandKind::Pause, CommandKind::Next]);
 
     let (_stream, stream_handle) = retry(OutputStream::try_default);
     let sink = Sink::try_new(&stream_handle).unwrap();
@@ -44,7 +48,7 @@ pub fn player(playlist: &Playlist, mut player_bus: PlayerBus) {
     loop {
         if sink.empty() {
             if let Some(track) = playlist.pop() {
-                let source = source(track.clone());
+                let source = source(track.clone(), &player_bus);
                 if source.is_some() {
                     playing_time = Some(Duration::ZERO);
                     player_bus.publish_message(Message::PlayerPlayingNewTrack(track));
@@ -59,7 +63,16 @@ pub fn player(playlist: &Playlist, mut player_bus: PlayerBus) {
                 thread::sleep(Duration::from_millis(200));
             }
         } else {
-            match command_channel.read_command() {
+            // Paused playback has no time to report, it only waits for the next command.
+            let command = if sink.is_paused() {
+                let command = command_channel.recv_command();
+                last_iteration_datetime = Instant::now();
+                command
+            } else {
+                command_channel.recv_command_timeout(ELAPSED_TICK)
+            };
+
+            match command {
                 Some(Command::Play) => {
                     sink.play();
                     player_bus.publish_message(Message::PlayerPlaying);
@@ -74,8 +87,6 @@ pub fn player(playlist: &Playlist, mut player_bus: PlayerBus) {
                 _ => {},
             };
 
-            thread::sleep(Duration::from_millis(50));
-
             if !sink.is_paused() {
                 debug!("[Player] playing time: {:?}, ({:?})", playing_time, last_iteration_datetime);
                 player_bus.publish_message(Message::PlayerElapsed(playing_time.unwrap_or(Duration::ZERO)));
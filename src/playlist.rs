@@ -4,8 +4,12 @@ use bytes::Bytes;
 use crossbeam_channel::{unbounded, Receiver, Sender};
 
 use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::util::lock;
 
 #[derive(Clone)]
+#[derive(Serialize, Deserialize)]
 pub struct Track {
     pub id: String,
     pub title: String,
@@ -13,6 +17,35 @@ pub struct Track {
     pub album_name: String,
     pub album_image: String, // Valid resolutions: 80x80, 160x160, 320x320, 640x640, 1280x1280
     pub duration: Duration,
+    pub metadata: TrackMetadata,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Serialize, Deserialize)]
+pub struct TrackMetadata {
+    pub album_id: String,
+    pub artist_id: String,
+    pub album_artist: String,
+    pub track_number: u32,
+    pub total_tracks: u32,
+    pub disc_number: u32,
+    pub total_discs: u32,
+    pub release_date: String,
+    pub isrc: String,
+    pub copyright: String,
+    // Unknown for UPnP tracks and files tagged without an advisory.
+    pub explicit: Option<bool>,
+    pub audio_quality: String,
+    pub replay_gain: Option<f64>,
+    pub peak: Option<f64>,
+}
+
+impl TrackMetadata {
+    pub fn year(&self) -> String {
+        self.release_date.chars().take(4).collect()
+    }
 }
 
 impl Track {
@@ -35,13 +68,28 @@ impl fmt::Debug for Track {
 
 #[derive(Debug)]
 #[derive(Clone)]
+#[derive(Serialize, Deserialize)]
 pub struct Cover {
     pub foreground: Option<String>,
     pub background: Option<String>,
+    pub palette: Option<Palette>,
+}
+
+// RGB colors picked from the cover, text and shadow are contrast checked against the darkened background.
+#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct Palette {
+    pub dominant: [u8; 3],
+    pub vibrant: [u8; 3],
+    pub muted: [u8; 3],
+    pub text: [u8; 3],
+    pub shadow: [u8; 3],
 }
 
 #[derive(Debug)]
 #[derive(Clone)]
+#[derive(Serialize, Deserialize)]
 pub struct BufferedCover {
     pub url: String,
     pub path: String,
@@ -55,17 +103,26 @@ impl BufferedCover {
 
 impl Cover {
     pub fn empty() -> Self {
-        Self { foreground: None, background: None }
+        Self { foreground: None, background: None, palette: None }
     }
 }
 
 #[derive(Clone)]
+#[derive(Serialize, Deserialize)]
 pub struct BufferedTrack {
     pub track: Track,
+    // Audio stays out of the event journal.
+    #[serde(skip)]
     pub stream: Bytes,
     pub cover: Cover,
 }
 
+impl BufferedTrack {
+    pub fn without_stream(self) -> Self {
+        Self { stream: Bytes::new(), ..self }
+    }
+}
+
 impl fmt::Debug for BufferedTrack {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "BufferedTrack: {}, {}, stream: {}, cover: {:?}", self.track.id, self.track.full_name(), !self.stream.is_empty(), self.cover)
@@ -107,12 +164,12 @@ impl Playlist {
 
             match self.receiver.recv() {
                 Ok(track) => {
-                    let old_force_lock = *self.force_lock.lock().unwrap();
+                    let old_force_lock = *lock(&self.force_lock);
                     
                     info!("[Playlist worker] Buffer track: {:?}", track);
                     match f(track.clone()) {
                         Some(buffered_track) => {
-                            let mut force_lock = self.force_lock.lock().unwrap();
+                            let mut force_lock = lock(&self.force_lock);
                             if !old_force_lock && force_lock.eq(&true) {
                                 info!("[Playlist worker] force lock ignore trakc {:?}", track);
                                 *force_lock = false;
@@ -132,9 +189,8 @@ impl Playlist {
 
     pub fn push(&self, tracks: Vec<Track>) {
         debug!("[Playlist] Push tracks: {:?}", tracks);
-        let _ = self.sender.lock().map(|sender| {
-            for t in &tracks { let _ = sender.send(t.clone()); }
-        });
+        let sender = lock(&self.sender);
+        for t in &tracks { let _ = sender.send(t.clone()); }
     }
 
     pub fn push_buffered(&self, tracks: Vec<BufferedTrack>) {
@@ -143,45 +199,43 @@ impl Playlist {
     }
     
     pub fn pop(&self) -> Option<BufferedTrack> {
-        self.sender.lock().map(|_| {
-            match self.buffered_receiver.try_recv() {
-                Ok(track) => {
-                    info!("[Playlist] Pop track: {:?}", track);
-                    Some(track)
-                },
-                Err(_) => None,
-            }
-        }).unwrap()
+        let _sender = lock(&self.sender);
+        match self.buffered_receiver.try_recv() {
+            Ok(track) => {
+                info!("[Playlist] Pop track: {:?}", track);
+                Some(track)
+            },
+            Err(_) => None,
+        }
     }
     
     pub fn push_force(&self, tracks: Vec<Track>) {
         debug!("[Playlist] Force push tracks: {:?}", tracks);
         
-        self.sender.lock().map(|sender| {
-            let mut force_lock = self.force_lock.lock().unwrap();
-            *force_lock = true;
+        let sender = lock(&self.sender);
+        let mut force_lock = lock(&self.force_lock);
+        *force_lock = true;
 
-            let mut existing_tracks: Vec<Track> = tracks;
-    
-            let mut next_buffered_exist = true;
-            while next_buffered_exist {
-                match self.buffered_receiver.recv_timeout(Duration::from_millis(300)) {
-                    Ok(buffered_track) => existing_tracks.push(buffered_track.track),
-                    Err(_) => next_buffered_exist = false,
-                }
+        let mut existing_tracks: Vec<Track> = tracks;
+
+        let mut next_buffered_exist = true;
+        while next_buffered_exist {
+            match self.buffered_receiver.recv_timeout(Duration::from_millis(300)) {
+                Ok(buffered_track) => existing_tracks.push(buffered_track.track),
+                Err(_) => next_buffered_exist = false,
             }
+        }
 
-            let mut next_exist = true;
-            while next_exist {
-                match self.receiver.recv_timeout(Duration::from_millis(300)) {
-                    Ok(track) => existing_tracks.push(track),
-                    Err(_) => next_exist = false,
-                }
+        let mut next_exist = true;
+        while next_exist {
+            match self.receiver.recv_timeout(Duration::from_millis(300)) {
+                Ok(track) => existing_tracks.push(track),
+                Err(_) => next_exist = false,
             }
+        }
 
-            for track in &existing_tracks { let _ = sender.send(track.clone()); }
+        for track in &existing_tracks { let _ = sender.send(track.clone()); }
 
-            debug!("[Playlist] playlist after push force: {:?}", existing_tracks);
-        }).unwrap();
+        debug!("[Playlist] playlist after push force: {:?}", existing_tracks);
     }
 }
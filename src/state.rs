@@ -1,14 +1,20 @@
-use std::{sync::{Arc, Mutex}, time::Duration};
+use std::{sync::{Arc, Mutex, Weak}, time::Duration};
 use std::collections::HashMap;
+use std::path::Path;
 
+use chrono::{DateTime, Local};
 use crossbeam_channel::{unbounded, Receiver, Sender};
 
-use log::{debug, info};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
 
-use crate::playlist::{BufferedCover, BufferedTrack, Cover, Track};
+use crate::journal::{Journal, JournalEvent};
+use crate::playlist::{BufferedCover, BufferedTrack, Cover, Palette, Track};
+use crate::util::lock;
 
 #[derive(Debug)]
 #[derive(Clone)]
+#[derive(Serialize, Deserialize)]
 pub enum Command {
     Play,
     Pause,
@@ -18,38 +24,61 @@ pub enum Command {
     PlayTrackForce(String),
     PlayAlbumForce(String),
     PlayArtistForce(String),
-    #[warn(dead_code)]
-    ShowScreen(String),
     AddTracksToPlaylist(Vec<Track>),
     AddTracksToPlaylistForce(Vec<Track>),
     AddBufferedTracksToPlaylist(Vec<BufferedTrack>),
     LoadLikedAlbum,
-    LoadCover(String)
+    LoadCover(String),
+    ReplayOfflineActions,
+    MarkAvailableOffline(String),
+}
+
+// Channels subscribe to command kinds, so a consumer can not listen to a command name that does not exist.
+#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum CommandKind {
+    Play,
+    Pause,
+    Next,
+    Like,
+    Radio,
+    PlayTrackForce,
+    PlayAlbumForce,
+    PlayArtistForce,
+    AddTracksToPlaylist,
+    AddTracksToPlaylistForce,
+    AddBufferedTracksToPlaylist,
+    LoadLikedAlbum,
+    LoadCover,
+    ReplayOfflineActions,
+    MarkAvailableOffline,
 }
 
 impl Command {
-    pub fn as_string(&self) -> String {
+    pub fn kind(&self) -> CommandKind {
         match self {
-            Command::Play => "Play".to_owned(),
-            Command::Pause => "Pause".to_owned(),
-            Command::Next => "Next".to_owned(),
-            Command::Like(_) => "Like".to_owned(),
-            Command::Radio(_) => "Radio".to_owned(),
-            Command::PlayTrackForce(_) => "PlayTrackForce".to_owned(),
-            Command::PlayAlbumForce(_) => "PlayAlbumForce".to_owned(),
-            Command::PlayArtistForce(_) => "PlayArtistForce".to_owned(),
-            Command::ShowScreen(_) => "ShowScreen".to_owned(),
-            Command::AddTracksToPlaylist(_) => "AddTracksToPlaylist".to_owned(),
-            Command::AddTracksToPlaylistForce(_) => "AddTracksToPlaylistForce".to_owned(),
-            Command::AddBufferedTracksToPlaylist(_) => "AddBufferedTracksToPlaylist".to_owned(),
-            Command::LoadLikedAlbum => "LoadLikedAlbum".to_owned(),
-            Command::LoadCover(_) => "LoadCover".to_owned(),
+            Command::Play => CommandKind::Play,
+            Command::Pause => CommandKind::Pause,
+            Command::Next => CommandKind::Next,
+            Command::Like(_) => CommandKind::Like,
+            Command::Radio(_) => CommandKind::Radio,
+            Command::PlayTrackForce(_) => CommandKind::PlayTrackForce,
+            Command::PlayAlbumForce(_) => CommandKind::PlayAlbumForce,
+            Command::PlayArtistForce(_) => CommandKind::PlayArtistForce,
+            Command::AddTracksToPlaylist(_) => CommandKind::AddTracksToPlaylist,
+            Command::AddTracksToPlaylistForce(_) => CommandKind::AddTracksToPlaylistForce,
+            Command::AddBufferedTracksToPlaylist(_) => CommandKind::AddBufferedTracksToPlaylist,
+            Command::LoadLikedAlbum => CommandKind::LoadLikedAlbum,
+            Command::LoadCover(_) => CommandKind::LoadCover,
+            Command::ReplayOfflineActions => CommandKind::ReplayOfflineActions,
+            Command::MarkAvailableOffline(_) => CommandKind::MarkAvailableOffline,
         }
     }
 }
 
 #[derive(Debug)]
 #[derive(Clone)]
+#[derive(Serialize, Deserialize)]
 pub enum Message {
     PlayerPlayingNewTrack(BufferedTrack),
     PlayerPlaying,
@@ -78,6 +107,21 @@ pub enum Message {
     TidalBackendStarted,
     TidalBackendLoginLinkCreated(String),
     TidalBackendInitialized,
+    TidalBackendReachable,
+    TidalBackendUnreachable,
+
+    UserSwitchOfflineMode(bool),
+    OfflineActionsQueued(usize),
+
+    UserMarkAvailableOffline(String),
+    OfflineSyncStarted(usize),
+    OfflineSyncTrackStarted(String),
+    OfflineSyncTrackFinished(bool),
+    OfflineSyncFinished,
+
+    UpnpBackendStarted,
+    UpnpBackendInitialized,
+    UpnpBackendUnavailable,
 
     RadioTracksLoaded,
     TrackLoaded,
@@ -85,6 +129,13 @@ pub enum Message {
     ArtistTracksLoaded,
     CoverLoaded(BufferedCover),
     CoverNeeded(String),
+
+    NotificationRaised(Notice),
+    UserDismissNotification(u64),
+
+    ModuleStarted(String),
+    ModuleFailed(String, String),
+    ModuleFinished(String),
 }
 
 #[derive(Debug)]
@@ -93,7 +144,131 @@ pub struct State {
     pub player: PlayerState,
     pub track: Option<TrackState>,
     pub backends: BackendsState,
+    pub offline: OfflineState,
+    pub offline_sync: OfflineSyncState,
     pub covers: Covers,
+    pub notifications: Notifications,
+    pub modules: Vec<ModuleHealth>,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+// A failure worth showing to the user, published by the module where it happened.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize, Deserialize)]
+pub struct Notice {
+    pub severity: Severity,
+    pub source: String,
+    pub message: String,
+}
+
+impl Notice {
+    pub fn warning(source: &str, message: String) -> Self {
+        Self { severity: Severity::Warning, source: source.to_string(), message }
+    }
+    pub fn error(source: &str, message: String) -> Self {
+        Self { severity: Severity::Error, source: source.to_string(), message }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+pub struct Notification {
+    pub id: u64,
+    pub severity: Severity,
+    pub source: String,
+    pub message: String,
+    pub time: DateTime<Local>,
+    pub dismissed: bool,
+}
+
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+#[derive(Default)]
+pub struct Notifications {
+    pub items: Vec<Notification>,
+    next_id: u64,
+}
+
+impl Notifications {
+    // Only the latest ones are kept, a failing track may be retried many times.
+    const MAX_ITEMS: usize = 50;
+
+    pub fn latest_active(&self) -> Option<&Notification> {
+        self.items.iter().rev().find(|notification| !notification.dismissed)
+    }
+
+    fn add_and_build(&self, notice: Notice) -> Self {
+        let mut items = self.items.clone();
+        items.push(Notification {
+            id: self.next_id,
+            severity: notice.severity,
+            source: notice.source,
+            message: notice.message,
+            time: Local::now(),
+            dismissed: false,
+        });
+        if items.len() > Self::MAX_ITEMS {
+            items.remove(0);
+        }
+        Notifications { items, next_id: self.next_id + 1 }
+    }
+
+    fn dismiss_and_build(&self, id: u64) -> Self {
+        let items = self.items.iter()
+            .map(|notification| Notification { dismissed: notification.dismissed || notification.id == id, ..notification.clone() })
+            .collect();
+        Notifications { items, next_id: self.next_id }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+pub enum ModuleStatus {
+    Running,
+    // Panicked, waiting for the backoff before the restart.
+    Restarting(String),
+    // Returned by itself, e.g. the offline sync without the [ExporterFile] cache.
+    Finished,
+}
+
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+pub struct ModuleHealth {
+    pub name: String,
+    pub status: ModuleStatus,
+    pub restarts: u32,
+    pub last_restart: Option<DateTime<Local>>,
+}
+
+fn modules_started(modules: &[ModuleHealth], name: String) -> Vec<ModuleHealth> {
+    let mut modules = modules.to_vec();
+    match modules.iter_mut().find(|module| module.name == name) {
+        Some(module) => {
+            module.status = ModuleStatus::Running;
+            module.restarts += 1;
+            module.last_restart = Some(Local::now());
+        },
+        None => modules.push(ModuleHealth { name, status: ModuleStatus::Running, restarts: 0, last_restart: None }),
+    }
+    modules
+}
+
+fn modules_with_status(modules: &[ModuleHealth], name: &str, status: ModuleStatus) -> Vec<ModuleHealth> {
+    modules.iter()
+        .map(|module| match module.name == name {
+            true => ModuleHealth { status: status.clone(), ..module.clone() },
+            false => module.clone(),
+        })
+        .collect()
 }
 
 #[derive(Debug)]
@@ -107,8 +282,9 @@ impl Covers {
         Self { items: HashMap::new() }
     }
 
+    // Rendered covers may be removed by the cover cache cleanup, then the cover is needed again.
     pub fn get(&self, cover_url: String) -> Option<&String> {
-        self.items.get(&cover_url)
+        self.items.get(&cover_url).filter(|path| Path::new(path).exists())
     }
 
     fn add_and_build(&self, cover: BufferedCover) -> Self {
@@ -119,7 +295,7 @@ impl Covers {
 }
 
 #[derive(Debug)]
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum BackendState {
     Off,
     Initialization,
@@ -128,9 +304,44 @@ pub enum BackendState {
 }
 
 #[derive(Debug)]
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct BackendsState {
     pub tidal: BackendState,
+    pub upnp: BackendState,
+}
+
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+pub struct OfflineState {
+    pub manual: bool,
+    pub tidal_reachable: bool,
+    pub queued_actions: usize,
+}
+
+impl OfflineState {
+    pub fn enabled(&self) -> bool {
+        self.manual || !self.tidal_reachable
+    }
+
+    fn build(&self, manual: bool, tidal_reachable: bool, player_bus: &PlayerBus) -> Self {
+        let next = OfflineState { manual, tidal_reachable, ..self.clone() };
+        if self.enabled() && !next.enabled() {
+            player_bus.publish_command(Command::ReplayOfflineActions);
+        }
+        next
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+pub struct OfflineSyncState {
+    pub running: bool,
+    pub total: usize,
+    pub done: usize,
+    pub failed: usize,
+    pub current: Option<String>,
+    pub last_finished: Option<DateTime<Local>>,
 }
 
 #[derive(Debug)]
@@ -141,7 +352,7 @@ pub struct PlayerState {
 }
 
 #[derive(Debug)]
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum PlayerStateCase {
     Playing,
     Paused,
@@ -153,6 +364,7 @@ pub enum PlayerStateCase {
 pub struct TrackStateCover {
     pub foreground: Option<String>,
     pub background: Option<String>,
+    pub palette: Option<Palette>,
 }
 
 impl From<Cover> for TrackStateCover {
@@ -160,6 +372,7 @@ impl From<Cover> for TrackStateCover {
         Self { 
             foreground: cover.foreground,
             background: cover.background,
+            palette: cover.palette,
         }
     }
 }
@@ -204,59 +417,210 @@ impl From<Track> for TrackState {
 #[derive(Debug)]
 #[derive(Clone)]
 pub struct BroadcastChannel {
-    message_sender: Sender<Command>, 
     message_receiver: Receiver<Command>,
-    commands: Vec<String>
+    // Dropped with the last clone of the channel, then the broadcast forgets the route.
+    _consumer: Arc<()>,
 }
 
 impl BroadcastChannel {
-    pub fn read_command(&self) -> Option<Command> {
-        let command = self.message_receiver.try_recv();
-        debug!("[PlayerBus] Command readed: {:?}", command);
-
-        match command {
-            Ok(command) => Some(command),
-            Err(_) => None,
-        }
+    // Blocks until a command arrives, the broadcast keeps the sender while the channel exists so it is never disconnected.
+    pub fn recv_command(&self) -> Option<Command> {
+        let command = self.message_receiver.recv().ok();
+        debug!("[PlayerBus] Command received: {:?}", command);
+        command
     }
-    fn send(&self, command: Command) {
-        let _ = self.message_sender.send(command);
+    // None when nothing arrived in time, lets a consumer wake for commands and still run periodic work.
+    pub fn recv_command_timeout(&self, timeout: Duration) -> Option<Command> {
+        let command = self.message_receiver.recv_timeout(timeout).ok();
+        if command.is_some() {
+            debug!("[PlayerBus] Command received: {:?}", command);
+        }
+        command
     }
 }
 
+#[derive(Debug)]
+struct Route {
+    message_sender: Sender<Command>,
+    commands: Vec<CommandKind>,
+    delivered: usize,
+    consumer: Weak<()>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct CommandRoute {
+    pub commands: Vec<CommandKind>,
+    pub delivered: usize,
+    pub pending: usize,
+}
+
 #[derive(Debug)]
 #[derive(Clone)]
 pub struct Broadcast {
-    channels: Arc<Mutex<Vec<BroadcastChannel>>>,
+    routes: Arc<Mutex<Vec<Route>>>,
 }
 
 impl Broadcast {
     fn init() -> Broadcast {
         Self {
-            channels: Arc::new(Mutex::new(vec![])),
+            routes: Arc::new(Mutex::new(vec![])),
         }
     }
-    pub fn register(&mut self, commands: Vec<String>) -> BroadcastChannel {
+    pub fn register(&mut self, commands: Vec<CommandKind>) -> BroadcastChannel {
         let (message_sender, message_receiver): (Sender<Command>, Receiver<Command>) = unbounded();
-        let channel = BroadcastChannel {
-            message_receiver,
+        let consumer = Arc::new(());
+        lock(&self.routes).push(Route {
             message_sender,
             commands: commands.clone(),
-        };
-        let new_channels = self.channels.clone();
-        new_channels.lock().unwrap().push(channel.clone());
+            delivered: 0,
+            consumer: Arc::downgrade(&consumer),
+        });
 
         info!("[PlayerBus] new channel on broadcast registred, commands: {:?}", commands);
-        channel
+        BroadcastChannel { message_receiver, _consumer: consumer }
     }
     fn send(&self, command: Command) {
-        for channel in self.channels.lock().unwrap().iter() {
-            if channel.commands.contains(&command.as_string()) {
-                channel.send(command.clone());
-                info!("[PlayerBus] broadcast event sended, command: {:?}, channel: {:?}", command, channel);
+        let kind = command.kind();
+        let mut routes = lock(&self.routes);
+        // A restarted module registers again, the channel of the stopped one is gone.
+        routes.retain(|route| {
+            let alive = route.consumer.strong_count() > 0;
+            if !alive {
+                info!("[PlayerBus] channel dropped, commands: {:?}", route.commands);
+            }
+            alive
+        });
+
+        let mut consumers = 0;
+        for route in routes.iter_mut().filter(|route| route.commands.contains(&kind)) {
+            if route.message_sender.send(command.clone()).is_ok() {
+                route.delivered += 1;
             }
+            consumers += 1;
+            debug!("[PlayerBus] broadcast event sended, command: {:?}, channel: {:?}", kind, route.commands);
+        }
+
+        if consumers == 0 {
+            warn!("[PlayerBus] command without consumer dropped: {:?}", kind);
         }
     }
+    fn routes(&self) -> Vec<CommandRoute> {
+        lock(&self.routes).iter()
+            .filter(|route| route.consumer.strong_count() > 0)
+            .map(|route| CommandRoute {
+                commands: route.commands.clone(),
+                delivered: route.delivered,
+                pending: route.message_sender.len(),
+            })
+            .collect()
+    }
+}
+
+// Subscribers learn what changed and read the state they are interested in.
+#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum StateEventKind {
+    TrackChanged,
+    PlaybackChanged,
+    Elapsed,
+    BackendChanged,
+    OfflineChanged,
+    // Tracks were added to the queue or the next one was taken from it.
+    QueueChanged,
+    NotificationsChanged,
+    ModulesChanged,
+}
+
+impl StateEventKind {
+    pub fn all() -> Vec<StateEventKind> {
+        vec![
+            StateEventKind::TrackChanged,
+            StateEventKind::PlaybackChanged,
+            StateEventKind::Elapsed,
+            StateEventKind::BackendChanged,
+            StateEventKind::OfflineChanged,
+            StateEventKind::QueueChanged,
+            StateEventKind::NotificationsChanged,
+            StateEventKind::ModulesChanged,
+        ]
+    }
+}
+
+// The parts of the state events are derived from, compared before and after each message.
+#[derive(PartialEq)]
+struct ObservedState {
+    track_id: Option<String>,
+    case: PlayerStateCase,
+    playing_time: Option<Duration>,
+    backends: BackendsState,
+    offline: OfflineState,
+    notifications: Notifications,
+    modules: Vec<ModuleHealth>,
+}
+
+impl ObservedState {
+    fn of(state: &State) -> Self {
+        Self {
+            track_id: state.track.as_ref().map(|track| track.id.clone()),
+            case: state.player.case.clone(),
+            playing_time: state.player.playing_time,
+            backends: state.backends.clone(),
+            offline: state.offline.clone(),
+            notifications: state.notifications.clone(),
+            modules: state.modules.clone(),
+        }
+    }
+
+    fn events(&self, next: &ObservedState, new_track: bool, queue_changed: bool) -> Vec<StateEventKind> {
+        let mut events: Vec<StateEventKind> = vec![];
+        if new_track || self.track_id != next.track_id {
+            events.push(StateEventKind::TrackChanged);
+        }
+        if self.case != next.case {
+            events.push(StateEventKind::PlaybackChanged);
+        }
+        if next.playing_time.is_some() && self.playing_time != next.playing_time {
+            events.push(StateEventKind::Elapsed);
+        }
+        if self.backends != next.backends {
+            events.push(StateEventKind::BackendChanged);
+        }
+        if self.offline != next.offline {
+            events.push(StateEventKind::OfflineChanged);
+        }
+        if queue_changed {
+            events.push(StateEventKind::QueueChanged);
+        }
+        if self.notifications != next.notifications {
+            events.push(StateEventKind::NotificationsChanged);
+        }
+        if self.modules != next.modules {
+            events.push(StateEventKind::ModulesChanged);
+        }
+        events
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+struct Subscriber {
+    kinds: Vec<StateEventKind>,
+    sender: Sender<StateEventKind>,
+}
+
+#[derive(Debug)]
+pub struct Subscription {
+    receiver: Receiver<StateEventKind>,
+}
+
+impl Subscription {
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<StateEventKind> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+    pub fn try_recv(&self) -> Option<StateEventKind> {
+        self.receiver.try_recv().ok()
+    }
 }
 
 #[derive(Debug)]
@@ -264,6 +628,8 @@ impl Broadcast {
 pub struct PlayerBus {
     broadcast: Broadcast,
     state: Arc<Mutex<State>>,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    journal: Journal,
 }
 
 impl State {
@@ -275,23 +641,45 @@ impl State {
             },
             track: None,
             backends: BackendsState { 
-                tidal: BackendState::Off
+                tidal: BackendState::Off,
+                upnp: BackendState::Off,
             },
+            offline: OfflineState {
+                manual: false,
+                tidal_reachable: true,
+                queued_actions: 0,
+            },
+            offline_sync: OfflineSyncState::default(),
             covers: Covers::init(),
+            notifications: Notifications::default(),
+            modules: vec![],
         }
     }
 }
 
 impl PlayerBus {
-    pub fn new() -> PlayerBus {
+    pub fn new(journal: Journal) -> PlayerBus {
         PlayerBus{
             broadcast: Broadcast::init(),
             state: Arc::new(Mutex::new(State::default_state())),
+            subscribers: Arc::new(Mutex::new(vec![])),
+            journal,
         }
     }
 
     pub fn publish_message(&self, message: Message) {
-        let mut state = self.state.lock().unwrap();
+        let mut state = lock(&self.state);
+        // Recorded under the state lock, so a replay applies the messages in the order they changed the state.
+        self.journal.record(JournalEvent::Message(Box::new(message.clone())));
+        let observed = ObservedState::of(&state);
+        let new_track = matches!(message, Message::PlayerPlayingNewTrack(_));
+        let queue_changed = matches!(message,
+            Message::PlayerPlayingNewTrack(_)
+            | Message::PlayerQueueIsEmpty
+            | Message::TrackDiscovered(_)
+            | Message::TracksDiscoveredWithHighPriority(_)
+            | Message::TrackDiscoveredLocally(_)
+        );
 
         let prev_state = state.clone();
         let next_state = match message {
@@ -303,8 +691,24 @@ impl PlayerBus {
             Message::UserPlay => { self.publish_command(Command::Play); prev_state },
             Message::UserPause => { self.publish_command(Command::Pause); prev_state },
             Message::UserPlayNext => { self.publish_command(Command::Next); prev_state },
-            Message::UserLike => { self.publish_command(Command::Like(prev_state.track.clone().unwrap().id)); prev_state },
-            Message::UserLoadRadio => { self.publish_command(Command::Pause); self.publish_command(Command::Radio(prev_state.track.clone().unwrap().id)); prev_state },
+            // A journal replay may start after the track was announced, there is nothing to like then.
+            Message::UserLike => {
+                if let Some(track) = &prev_state.track {
+                    self.publish_command(Command::Like(track.id.clone()));
+                } else {
+                    warn!("[PlayerBus] like skipped, no track is playing");
+                }
+                prev_state
+            },
+            Message::UserLoadRadio => {
+                if let Some(track) = &prev_state.track {
+                    self.publish_command(Command::Pause);
+                    self.publish_command(Command::Radio(track.id.clone()));
+                } else {
+                    warn!("[PlayerBus] radio skipped, no track is playing");
+                }
+                prev_state
+            },
             Message::UserPlayTrack(track) => { self.publish_command(Command::Pause); self.publish_command(Command::PlayTrackForce(track)); prev_state },
             Message::UserPlayAlbum(track) => { self.publish_command(Command::Pause); self.publish_command(Command::PlayAlbumForce(track)); prev_state },
             Message::UserPlayArtist(track) => { self.publish_command(Command::Pause); self.publish_command(Command::PlayArtistForce(track)); prev_state },
@@ -312,32 +716,89 @@ impl PlayerBus {
             Message::TrackDiscovered(track) => { self.publish_command(Command::AddTracksToPlaylist(vec![track])); prev_state },
             Message::TracksDiscoveredWithHighPriority(tracks) => { self.publish_command(Command::AddTracksToPlaylistForce(tracks)); prev_state },
             Message::TrackDiscoveredLocally(buffered_stream) => { self.publish_command(Command::AddBufferedTracksToPlaylist(vec![buffered_stream])); prev_state },
-            Message::UserClickActions => { self.publish_command(Command::ShowScreen("/actions".to_string())); prev_state },
-            Message::UserClickBackToPlayer => { self.publish_command(Command::ShowScreen("/player".to_string())); prev_state },
-            Message::UserClickLikedAlbumsButton => { self.publish_command(Command::ShowScreen("/browse".to_string())); self.publish_command(Command::LoadLikedAlbum); prev_state },
+            // Screens are switched by the GUI itself, nothing listens for them on the bus.
+            Message::UserClickActions => prev_state,
+            Message::UserClickBackToPlayer => prev_state,
+            Message::UserClickLikedAlbumsButton => { self.publish_command(Command::LoadLikedAlbum); prev_state },
             Message::TidalBackendStarted => State { backends: BackendsState { tidal: BackendState::Initialization, ..prev_state.backends }, ..prev_state },
             Message::TidalBackendLoginLinkCreated(login_link) =>  State { backends: BackendsState { tidal: BackendState::WaitingForLoginByLink(login_link), ..prev_state.backends }, ..prev_state },
-            Message::TidalBackendInitialized => { self.publish_command(Command::ShowScreen("/player".to_string())); State { backends: BackendsState { tidal: BackendState::Ready, ..prev_state.backends }, ..prev_state }},
+            Message::TidalBackendInitialized => State { backends: BackendsState { tidal: BackendState::Ready, ..prev_state.backends }, ..prev_state },
+            Message::TidalBackendReachable => State { offline: prev_state.offline.build(prev_state.offline.manual, true, self), ..prev_state },
+            Message::TidalBackendUnreachable => State { offline: prev_state.offline.build(prev_state.offline.manual, false, self), ..prev_state },
+            Message::UserSwitchOfflineMode(enabled) => State { offline: prev_state.offline.build(enabled, prev_state.offline.tidal_reachable, self), ..prev_state },
+            Message::OfflineActionsQueued(count) => State { offline: OfflineState { queued_actions: count, ..prev_state.offline }, ..prev_state },
+            Message::UserMarkAvailableOffline(url) => { self.publish_command(Command::MarkAvailableOffline(url)); prev_state },
+            Message::OfflineSyncStarted(total) => State { offline_sync: OfflineSyncState { running: true, total, done: 0, failed: 0, current: None, ..prev_state.offline_sync }, ..prev_state },
+            Message::OfflineSyncTrackStarted(track) => State { offline_sync: OfflineSyncState { current: Some(track), ..prev_state.offline_sync }, ..prev_state },
+            Message::OfflineSyncTrackFinished(success) => State { offline_sync: OfflineSyncState { 
+                done: prev_state.offline_sync.done + 1,
+                failed: prev_state.offline_sync.failed + usize::from(!success),
+                current: None,
+                ..prev_state.offline_sync
+            }, ..prev_state },
+            Message::OfflineSyncFinished => State { offline_sync: OfflineSyncState { running: false, current: None, last_finished: Some(Local::now()), ..prev_state.offline_sync }, ..prev_state },
+            Message::UpnpBackendStarted => State { backends: BackendsState { upnp: BackendState::Initialization, ..prev_state.backends }, ..prev_state },
+            Message::UpnpBackendInitialized => State { backends: BackendsState { upnp: BackendState::Ready, ..prev_state.backends }, ..prev_state },
+            Message::UpnpBackendUnavailable => State { backends: BackendsState { upnp: BackendState::Off, ..prev_state.backends }, ..prev_state },
             Message::RadioTracksLoaded => { self.publish_command(Command::Next); prev_state },
             Message::TrackLoaded => { self.publish_command(Command::Next); prev_state },
             Message::AlbumTracksLoaded => { self.publish_command(Command::Next); prev_state },
             Message::ArtistTracksLoaded => { self.publish_command(Command::Next); prev_state },
             Message::CoverNeeded(cover_url) => { self.publish_command(Command::LoadCover(cover_url)); prev_state },
             Message::CoverLoaded(cover) => State { covers: prev_state.covers.add_and_build(cover), ..prev_state },
+            Message::NotificationRaised(notice) => State { notifications: prev_state.notifications.add_and_build(notice), ..prev_state },
+            Message::UserDismissNotification(id) => State { notifications: prev_state.notifications.dismiss_and_build(id), ..prev_state },
+            Message::ModuleStarted(name) => State { modules: modules_started(&prev_state.modules, name), ..prev_state },
+            Message::ModuleFailed(name, reason) => State { modules: modules_with_status(&prev_state.modules, &name, ModuleStatus::Restarting(reason)), ..prev_state },
+            Message::ModuleFinished(name) => State { modules: modules_with_status(&prev_state.modules, &name, ModuleStatus::Finished), ..prev_state },
         };
 
+        let events = observed.events(&ObservedState::of(&next_state), new_track, queue_changed);
         *state = next_state;
+
+        // Sent under the state lock, so subscribers see the events in the order the state changed.
+        self.notify_subscribers(events);
+    }
+
+    // Every subscriber gets its own channel, dropped subscriptions are removed on the next event.
+    pub fn subscribe(&self, kinds: Vec<StateEventKind>) -> Subscription {
+        let (sender, receiver) = unbounded();
+        info!("[PlayerBus] new state subscription, events: {:?}", kinds);
+        lock(&self.subscribers).push(Subscriber { kinds, sender });
+        Subscription { receiver }
     }
 
-    pub fn register_command_channel(&mut self, commands: Vec<String>) -> BroadcastChannel {
+    fn notify_subscribers(&self, events: Vec<StateEventKind>) {
+        if events.is_empty() {
+            return;
+        }
+
+        lock(&self.subscribers).retain(|subscriber| {
+            events.iter()
+                .filter(|event| subscriber.kinds.contains(event))
+                .all(|event| subscriber.sender.send(*event).is_ok())
+        });
+    }
+
+    pub fn register_command_channel(&mut self, commands: Vec<CommandKind>) -> BroadcastChannel {
         self.broadcast.register(commands)
     }
 
+    // Delivered and still queued commands per registered channel.
+    pub fn command_routes(&self) -> Vec<CommandRoute> {
+        self.broadcast.routes()
+    }
+
     pub fn publish_command(&self, command: Command) {
+        self.journal.record(JournalEvent::Command(command.clone()));
         self.broadcast.send(command);
     }
 
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
     pub fn read_state(&self) -> State {
-        self.state.lock().unwrap().clone()
+        lock(&self.state).clone()
     }
 }
\ No newline at end of file
@@ -0,0 +1,82 @@
+use std::{any::Any, thread::{self, JoinHandle}, time::{Duration, Instant}};
+
+use log::{error, info};
+use thread_priority::{ThreadBuilderExt, ThreadPriority};
+
+use crate::state::{Message, Notice, PlayerBus};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// A module running this long has recovered, its next failure starts again with the initial backoff.
+const STABLE_RUN: Duration = Duration::from_secs(300);
+
+#[derive(Clone)]
+pub struct Supervisor {
+    player_bus: PlayerBus,
+}
+
+impl Supervisor {
+    pub fn new(player_bus: PlayerBus) -> Self {
+        Self { player_bus }
+    }
+
+    // Runs the module on its own thread and starts it again whenever it panics, a module that returns is done.
+    pub fn spawn<F>(&self, name: &'static str, priority: Option<ThreadPriority>, module: F) -> JoinHandle<()>
+    where
+        F: Fn() + Send + Clone + 'static,
+    {
+        let player_bus = self.player_bus.clone();
+
+        thread::Builder::new()
+            .name(format!("{} supervisor", name))
+            .spawn(move || {
+                let mut backoff = INITIAL_BACKOFF;
+
+                loop {
+                    info!("[Supervisor] start {}", name);
+                    player_bus.publish_message(Message::ModuleStarted(name.to_string()));
+                    let started = Instant::now();
+
+                    let Err(reason) = Self::run(name, priority, module.clone()) else {
+                        info!("[Supervisor] {} finished", name);
+                        player_bus.publish_message(Message::ModuleFinished(name.to_string()));
+                        return;
+                    };
+
+                    if started.elapsed() >= STABLE_RUN {
+                        backoff = INITIAL_BACKOFF;
+                    }
+                    error!("[Supervisor] {} {}, restart in {:?}", name, reason, backoff);
+                    player_bus.publish_message(Message::ModuleFailed(name.to_string(), reason.clone()));
+                    player_bus.publish_message(Message::NotificationRaised(Notice::error("Supervisor", format!("{} module {}, restarting", name, reason))));
+
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            })
+            .unwrap()
+    }
+
+    fn run<F>(name: &str, priority: Option<ThreadPriority>, module: F) -> Result<(), String>
+    where
+        F: Fn() + Send + 'static,
+    {
+        let builder = thread::Builder::new().name(format!("{} module", name));
+        let handle = match priority {
+            Some(priority) => builder.spawn_with_priority(priority, move |_| module()),
+            None => builder.spawn(module),
+        };
+
+        handle
+            .map_err(|err| format!("can not start: {}", err))?
+            .join()
+            .map_err(panic_reason)
+    }
+}
+
+fn panic_reason(payload: Box<dyn Any + Send>) -> String {
+    let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    format!("panicked: {}", message)
+}
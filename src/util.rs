@@ -0,0 +1,6 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+// A module that panicked while holding a lock is restarted by the supervisor, the data it shares stays usable.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}